@@ -0,0 +1,74 @@
+// Adler-32 as specified in RFC 1950 section 8.2.
+const BASE: u32 = 65521;
+// largest n such that 255 * n * (n + 1) / 2 + (n + 1) * (BASE - 1) fits in
+// 32 bits, i.e. how many bytes can be summed before reducing modulo BASE
+const NMAX: usize = 5552;
+
+/// running Adler-32 of the bytes passed to `update` so far.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Adler32 {
+    a: u32,
+    b: u32,
+}
+
+impl Adler32 {
+    pub fn new() -> Self {
+        Self { a: 1, b: 0 }
+    }
+
+    pub fn update(&mut self, buf: &[u8]) {
+        for chunk in buf.chunks(NMAX) {
+            for &byte in chunk {
+                self.a += u32::from(byte);
+                self.b += self.a;
+            }
+            self.a %= BASE;
+            self.b %= BASE;
+        }
+    }
+
+    pub fn sum(&self) -> u32 {
+        (self.b << 16) | self.a
+    }
+}
+
+impl Default for Adler32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// returns Adler-32 of `buf`.
+pub fn adler32(buf: &[u8]) -> u32 {
+    let mut adler = Adler32::new();
+    adler.update(buf);
+    adler.sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_input_yields_one() {
+        assert_eq!(adler32(&[]), 1);
+    }
+
+    #[test]
+    fn known_values() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11e60398);
+        // every byte 0xff overflows without the modulo at NMAX
+        assert_eq!(adler32(&vec![0xff; 100_000]), 0x149a302c);
+    }
+
+    #[test]
+    fn update_can_be_split() {
+        let data: Vec<u8> = (0..20000u32).map(|i| (i * 7) as u8).collect();
+
+        let mut split = Adler32::new();
+        for chunk in data.chunks(999) {
+            split.update(chunk);
+        }
+        assert_eq!(split.sum(), adler32(&data));
+    }
+}
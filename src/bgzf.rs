@@ -0,0 +1,550 @@
+// BGZF (blocked gzip) as used by SAM/BAM and tabix: a series of gzip members,
+// each holding at most 64 KiB of input and carrying its own compressed size in
+// a 'BC' extra subfield, so that a reader can jump from member to member and
+// seek to any member without decompressing what precedes it.
+//
+// Positions are expressed as virtual offsets: the offset of a member in the
+// compressed file shifted left by 16 bits, ORed with an offset into the
+// decompressed data of that member.
+
+use crate::bit_writer::BitWriter;
+use crate::compress::Deflater;
+use crate::crc32::Crc32;
+use crate::header::{ExtraSubfield, GzipFlags};
+use crate::{CompressOptions, DecompressOptions};
+use anyhow::{bail, ensure, Context, Result};
+use std::io::{Read, Seek, SeekFrom, Write};
+
+// input held by a block; same as htslib so that the compressed block always
+// fits in 64 KiB even when stored
+const BLOCK_DATA_SIZE: usize = 0xff00;
+// upper bound of a compressed block, i.e. BSIZE + 1
+const MAX_BLOCK_SIZE: usize = 0x10000;
+// ID1, ID2, CM, FLG, MTIME, XFL, OS, XLEN
+const FIXED_HEADER_SIZE: usize = 12;
+// fixed header followed by the 'BC' subfield
+const HEADER_SIZE: usize = FIXED_HEADER_SIZE + 6;
+// CRC32, ISIZE
+const TRAILER_SIZE: usize = 8;
+
+/// empty block marking the end of a BGZF file.
+pub const EOF_MARKER: [u8; 28] = [
+    0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, 0x42, 0x43, 0x02, 0x00,
+    0x1b, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// makes a virtual offset out of the offset of a block in the compressed file
+/// and an offset into its decompressed data.
+pub fn virtual_offset(block_offset: u64, within_block: u16) -> u64 {
+    (block_offset << 16) | u64::from(within_block)
+}
+
+/// `.gzi` index: the compressed and the uncompressed offset of the start of
+/// each block but the first one.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GziIndex {
+    // (compressed offset, uncompressed offset) in increasing order
+    entries: Vec<(u64, u64)>,
+}
+
+impl GziIndex {
+    pub fn entries(&self) -> &[(u64, u64)] {
+        &self.entries
+    }
+
+    /// builds an index by walking over the blocks of a BGZF file; only
+    /// headers and trailers are read.
+    pub fn build<R>(reader: &mut R) -> Result<Self>
+    where
+        R: Read + Seek,
+    {
+        let mut entries = vec![];
+        let start = reader.stream_position()?;
+        let mut compressed = start;
+        let mut uncompressed = 0;
+
+        while let Some(block_size) = read_block_size(reader)? {
+            // ISIZE
+            reader.seek(SeekFrom::Start(compressed + block_size as u64 - 4))?;
+            let mut isize = [0; 4];
+            reader.read_exact(&mut isize)?;
+
+            // the first block isn't indexed
+            if compressed > start {
+                entries.push((compressed, uncompressed));
+            }
+            compressed += block_size as u64;
+            uncompressed += u64::from(u32::from_le_bytes(isize));
+        }
+
+        // htslib doesn't index the EOF marker
+        if entries.last().is_some_and(|&(_, u)| u == uncompressed) {
+            entries.pop();
+        }
+
+        Ok(Self { entries })
+    }
+
+    pub fn read_from<R>(reader: &mut R) -> Result<Self>
+    where
+        R: Read,
+    {
+        let mut buf = [0; 8];
+        reader
+            .read_exact(&mut buf)
+            .context("failed to read .gzi index")?;
+        let count = u64::from_le_bytes(buf);
+
+        let mut entries = vec![];
+        for _ in 0..count {
+            reader
+                .read_exact(&mut buf)
+                .context("truncated .gzi index")?;
+            let compressed = u64::from_le_bytes(buf);
+            reader
+                .read_exact(&mut buf)
+                .context("truncated .gzi index")?;
+            let uncompressed = u64::from_le_bytes(buf);
+
+            if let Some(&(c, u)) = entries.last() {
+                ensure!(
+                    c < compressed && u <= uncompressed,
+                    ".gzi index: offsets must increase; ({}, {}) follows ({}, {})",
+                    compressed,
+                    uncompressed,
+                    c,
+                    u,
+                );
+            }
+            entries.push((compressed, uncompressed));
+        }
+
+        Ok(Self { entries })
+    }
+
+    pub fn write_to<W>(&self, writer: &mut W) -> Result<()>
+    where
+        W: Write,
+    {
+        writer.write_all(&(self.entries.len() as u64).to_le_bytes())?;
+        for &(compressed, uncompressed) in &self.entries {
+            writer.write_all(&compressed.to_le_bytes())?;
+            writer.write_all(&uncompressed.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// virtual offset of the given position in the decompressed data.
+    pub fn virtual_offset_of(&self, position: u64) -> u64 {
+        let i = self.entries.partition_point(|&(_, u)| u <= position);
+        let (compressed, uncompressed) = match i {
+            0 => (0, 0),
+            _ => self.entries[i - 1],
+        };
+        // blocks never hold more than 64 KiB
+        virtual_offset(compressed, (position - uncompressed) as u16)
+    }
+}
+
+// reads the header of the block at the current position and returns its total
+// size, leaving the position just after the fixed part of the header; None at
+// the end of input
+fn read_block_size<R>(reader: &mut R) -> Result<Option<usize>>
+where
+    R: Read,
+{
+    let mut fixed = [0; FIXED_HEADER_SIZE];
+    let read = read_up_to(reader, &mut fixed)?;
+    if read == 0 {
+        return Ok(None);
+    }
+    ensure!(read == FIXED_HEADER_SIZE, "BGZF: truncated block header");
+    ensure!(
+        fixed[..3] == [0x1f, 0x8b, 0x08],
+        "BGZF: block doesn't start with a gzip header",
+    );
+    ensure!(
+        GzipFlags(fixed[3]).has_extra(),
+        "BGZF: block has no extra field",
+    );
+
+    let xlen = usize::from(u16::from_le_bytes([fixed[10], fixed[11]]));
+    let mut extra = vec![0; xlen];
+    reader.read_exact(&mut extra)?;
+
+    let subfields = crate::header::parse_subfields(&extra)?;
+    let bsize = match subfields.iter().find(|s| s.id == ExtraSubfield::BGZF) {
+        Some(subfield) if subfield.data.len() == 2 => {
+            u16::from_le_bytes([subfield.data[0], subfield.data[1]])
+        }
+        _ => bail!("BGZF: block has no valid 'BC' subfield"),
+    };
+
+    let block_size = usize::from(bsize) + 1;
+    ensure!(
+        block_size >= FIXED_HEADER_SIZE + xlen + TRAILER_SIZE,
+        "BGZF: BSIZE = {} is too small",
+        bsize,
+    );
+    Ok(Some(block_size))
+}
+
+fn read_up_to<R>(reader: &mut R, buf: &mut [u8]) -> Result<usize>
+where
+    R: Read,
+{
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(read)
+}
+
+/// Compressor into BGZF that implements `Write`.
+///
+/// Every block is compressed independently with `opts`; `threads` is ignored.
+/// Call `finish()` to write the last block and the EOF marker.
+pub struct BgzfWriter<W> {
+    writer: W,
+    opts: CompressOptions,
+    buf: Vec<u8>,
+    index: GziIndex,
+    compressed: u64,
+    uncompressed: u64,
+}
+
+impl<W> BgzfWriter<W>
+where
+    W: Write,
+{
+    pub fn new(writer: W, opts: CompressOptions) -> Self {
+        Self {
+            writer,
+            opts,
+            buf: Vec::with_capacity(BLOCK_DATA_SIZE),
+            index: GziIndex::default(),
+            compressed: 0,
+            uncompressed: 0,
+        }
+    }
+
+    /// virtual offset at which the next byte written will be found.
+    pub fn virtual_offset(&self) -> u64 {
+        virtual_offset(self.compressed, self.buf.len() as u16)
+    }
+
+    /// index of the blocks written so far.
+    pub fn index(&self) -> &GziIndex {
+        &self.index
+    }
+
+    // writes buffered input as a block
+    fn write_block(&mut self) -> Result<()> {
+        let mut deflater = Deflater::new(&self.opts);
+        let mut body = BitWriter::new(Vec::with_capacity(self.buf.len()));
+        deflater.compress_block(&mut body, &self.buf, true)?;
+        let body = body.finish()?;
+
+        let block_size = HEADER_SIZE + body.len() + TRAILER_SIZE;
+        // can't happen as stored blocks fit
+        ensure!(
+            block_size <= MAX_BLOCK_SIZE,
+            "BGZF: compressed block too large",
+        );
+
+        let mut crc = Crc32::new();
+        crc.update(&self.buf);
+
+        let mut block = Vec::with_capacity(block_size);
+        // ID1, ID2, CM, FLG, MTIME
+        block.extend_from_slice(&[0x1f, 0x8b, 0x08, GzipFlags::EXTRA, 0, 0, 0, 0]);
+        // XFL, OS (unknown) as htslib does, XLEN
+        block.extend_from_slice(&[0, 0xff, 6, 0]);
+        // SI1, SI2, SLEN, BSIZE
+        block.extend_from_slice(&ExtraSubfield::BGZF);
+        block.extend_from_slice(&2u16.to_le_bytes());
+        block.extend_from_slice(&((block_size - 1) as u16).to_le_bytes());
+        block.extend_from_slice(&body);
+        block.extend_from_slice(&crc.sum().to_le_bytes());
+        block.extend_from_slice(&(self.buf.len() as u32).to_le_bytes());
+        self.writer.write_all(&block)?;
+
+        if self.compressed > 0 {
+            self.index
+                .entries
+                .push((self.compressed, self.uncompressed));
+        }
+        self.compressed += block_size as u64;
+        self.uncompressed += self.buf.len() as u64;
+        self.buf.clear();
+
+        Ok(())
+    }
+
+    /// writes the last block and the EOF marker; returns the inner writer and
+    /// the index of the blocks.
+    pub fn finish(mut self) -> Result<(W, GziIndex)> {
+        if !self.buf.is_empty() {
+            self.write_block()?;
+        }
+        self.writer.write_all(&EOF_MARKER)?;
+        self.writer.flush()?;
+
+        Ok((self.writer, self.index))
+    }
+}
+
+impl<W> Write for BgzfWriter<W>
+where
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let amount = std::cmp::min(buf.len(), BLOCK_DATA_SIZE - self.buf.len());
+        self.buf.extend_from_slice(&buf[..amount]);
+
+        if self.buf.len() == BLOCK_DATA_SIZE {
+            self.write_block().map_err(crate::into_io_error)?;
+        }
+
+        Ok(amount)
+    }
+
+    /// ends the current block so that everything written so far can be read
+    fn flush(&mut self) -> std::io::Result<()> {
+        if !self.buf.is_empty() {
+            self.write_block().map_err(crate::into_io_error)?;
+        }
+        self.writer.flush()
+    }
+}
+
+/// Decompressor of BGZF that implements `Read` and can seek by virtual
+/// offset.
+///
+/// Blocks are located by BSIZE and decompressed one at a time.
+pub struct BgzfReader<R> {
+    reader: R,
+    // offset of the current block in the compressed file
+    block_offset: u64,
+    block_size: usize,
+    data: Vec<u8>,
+    pos: usize,
+}
+
+impl<R> BgzfReader<R>
+where
+    R: Read + Seek,
+{
+    pub fn new(mut reader: R) -> Result<Self> {
+        let block_offset = reader.stream_position()?;
+        Ok(Self {
+            reader,
+            block_offset,
+            block_size: 0,
+            data: vec![],
+            pos: 0,
+        })
+    }
+
+    /// virtual offset of the next byte to be read.
+    pub fn virtual_offset(&self) -> u64 {
+        if self.pos == self.data.len() {
+            // the next byte is at the start of the next block
+            virtual_offset(self.block_offset + self.block_size as u64, 0)
+        } else {
+            virtual_offset(self.block_offset, self.pos as u16)
+        }
+    }
+
+    /// moves to the given virtual offset.
+    pub fn seek_virtual(&mut self, offset: u64) -> Result<()> {
+        let block_offset = offset >> 16;
+        let within_block = (offset & 0xffff) as usize;
+
+        self.reader.seek(SeekFrom::Start(block_offset))?;
+        self.block_offset = block_offset;
+        self.block_size = 0;
+        self.read_block()?;
+
+        ensure!(
+            within_block <= self.data.len(),
+            "BGZF: virtual offset {:#x} points past the end of its block ({} bytes)",
+            offset,
+            self.data.len(),
+        );
+        self.pos = within_block;
+        Ok(())
+    }
+
+    /// moves to the given position in the decompressed data.
+    pub fn seek_uncompressed(&mut self, position: u64, index: &GziIndex) -> Result<()> {
+        self.seek_virtual(index.virtual_offset_of(position))
+    }
+
+    // replaces `self.data` with the contents of the block starting at the
+    // current position of `self.reader`; leaves it empty at the end of input
+    fn read_block(&mut self) -> Result<()> {
+        self.data.clear();
+        self.pos = 0;
+
+        let block_offset = self.reader.stream_position()?;
+        let block_size = match read_block_size(&mut self.reader)? {
+            Some(size) => size,
+            None => {
+                self.block_offset = block_offset;
+                self.block_size = 0;
+                return Ok(());
+            }
+        };
+
+        // decompress the whole member from memory
+        self.reader.seek(SeekFrom::Start(block_offset))?;
+        let mut block = vec![0; block_size];
+        self.reader
+            .read_exact(&mut block)
+            .context("BGZF: truncated block")?;
+
+        let opts = DecompressOptions {
+            show_header: false,
+            no_emit: false,
+        };
+        let headers = crate::decompress::decompress(&mut &block[..], &mut self.data, &opts)
+            .with_context(|| format!("BGZF: broken block at offset {}", block_offset))?;
+        ensure!(
+            headers.len() == 1,
+            "BGZF: block at offset {} holds more than one member",
+            block_offset,
+        );
+
+        self.block_offset = block_offset;
+        self.block_size = block_size;
+        Ok(())
+    }
+}
+
+impl<R> Read for BgzfReader<R>
+where
+    R: Read + Seek,
+{
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        // skip empty blocks such as the EOF marker
+        while self.pos == self.data.len() {
+            self.reader
+                .seek(SeekFrom::Start(self.block_offset + self.block_size as u64))?;
+            self.read_block().map_err(crate::into_io_error)?;
+            if self.block_size == 0 {
+                return Ok(0);
+            }
+        }
+
+        let amount = std::cmp::min(buf.len(), self.data.len() - self.pos);
+        buf[..amount].copy_from_slice(&self.data[self.pos..self.pos + amount]);
+        self.pos += amount;
+        Ok(amount)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::sample;
+    use std::io::Cursor;
+
+    fn compress(data: &[u8]) -> (Vec<u8>, GziIndex) {
+        let mut writer = BgzfWriter::new(vec![], Default::default());
+        writer.write_all(data).unwrap();
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn output_is_valid_multi_member_gzip() {
+        let data = sample(40000);
+        let (compressed, _) = compress(&data);
+        assert!(compressed.ends_with(&EOF_MARKER));
+
+        let mut decompressed = vec![];
+        crate::GzDecoder::new(&compressed[..])
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, data);
+    }
+
+    #[test]
+    fn reader_follows_blocks() {
+        let data = sample(40000);
+        let (compressed, _) = compress(&data);
+
+        let mut reader = BgzfReader::new(Cursor::new(&compressed)).unwrap();
+        let mut decompressed = vec![];
+        reader.read_to_end(&mut decompressed).unwrap();
+        assert_eq!(decompressed, data);
+    }
+
+    #[test]
+    fn built_index_matches_written_index() {
+        let data = sample(40000);
+        let (compressed, index) = compress(&data);
+        assert!(index.entries().len() >= 3);
+
+        let built = GziIndex::build(&mut Cursor::new(&compressed)).unwrap();
+        assert_eq!(built, index);
+
+        // offsets are those in the file, but the first block is still left
+        // out when it doesn't start the file
+        let mut prefixed = Cursor::new([&[0; 100][..], &compressed].concat());
+        prefixed.set_position(100);
+        let built = GziIndex::build(&mut prefixed).unwrap();
+        assert_eq!(built.entries().len(), index.entries().len());
+        for (&(c, u), &(expected_c, expected_u)) in built.entries().iter().zip(index.entries()) {
+            assert_eq!((c, u), (expected_c + 100, expected_u));
+        }
+
+        let mut serialized = vec![];
+        index.write_to(&mut serialized).unwrap();
+        assert_eq!(serialized.len(), 8 + 16 * index.entries().len());
+        assert_eq!(GziIndex::read_from(&mut &serialized[..]).unwrap(), index);
+    }
+
+    #[test]
+    fn seeks_by_virtual_offset() {
+        let data = sample(40000);
+
+        let mut writer = BgzfWriter::new(vec![], Default::default());
+        let mut offsets = vec![];
+        for line in data.split_inclusive(|&b| b == b'\n').step_by(997) {
+            offsets.push((writer.virtual_offset(), line.to_vec()));
+            writer.write_all(line).unwrap();
+            writer.write_all(&vec![b'x'; 1500]).unwrap();
+        }
+        let (compressed, _) = writer.finish().unwrap();
+
+        let mut reader = BgzfReader::new(Cursor::new(&compressed)).unwrap();
+        for (offset, line) in offsets.iter().rev() {
+            reader.seek_virtual(*offset).unwrap();
+            assert_eq!(reader.virtual_offset(), *offset);
+
+            let mut buf = vec![0; line.len()];
+            reader.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf, line);
+        }
+    }
+
+    #[test]
+    fn seeks_by_uncompressed_position() {
+        let data = sample(40000);
+        let (compressed, index) = compress(&data);
+
+        let mut reader = BgzfReader::new(Cursor::new(&compressed)).unwrap();
+        for position in [0, 1, 65279, 65280, 100000, data.len() - 1] {
+            reader.seek_uncompressed(position as u64, &index).unwrap();
+            let mut byte = [0];
+            reader.read_exact(&mut byte).unwrap();
+            assert_eq!(byte[0], data[position], "position = {}", position);
+        }
+    }
+}
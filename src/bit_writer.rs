@@ -0,0 +1,204 @@
+use std::io::{Result, Write};
+
+// bytes accumulated before handing them to the underlying writer
+const BUF_SIZE: usize = 8192;
+
+/// Bit sink counterpart of `reader::Reader`.
+///
+/// Bits are packed starting from the least significant bit of each byte, as
+/// required by RFC 1951 section 3.1.1.
+pub struct BitWriter<W> {
+    writer: W,
+    buf: Vec<u8>,
+    // pending bits that don't fill a byte yet; always less than 8 of them
+    current: u64,
+    pos: u8,
+    // bits already moved into `buf` or `writer`
+    written: u64,
+}
+
+impl<W> BitWriter<W>
+where
+    W: Write,
+{
+    pub fn new(w: W) -> Self {
+        Self {
+            writer: w,
+            buf: Vec::with_capacity(BUF_SIZE),
+            current: 0,
+            pos: 0,
+            written: 0,
+        }
+    }
+
+    /// writes the lowest `bits` bits of `value`, from lsb to msb.
+    ///
+    /// This is how numbers other than Huffman codes are packed, i.e. the
+    /// inverse of `read_number_le`.
+    pub fn write_bits(&mut self, value: u32, bits: u8) -> Result<()> {
+        debug_assert!(bits <= 32);
+        debug_assert!(bits == 32 || value >> bits == 0);
+
+        self.current |= u64::from(value) << self.pos;
+        self.pos += bits;
+
+        while self.pos >= 8 {
+            self.buf.push(self.current as u8);
+            self.current >>= 8;
+            self.pos -= 8;
+            self.written += 8;
+        }
+
+        if self.buf.len() >= BUF_SIZE {
+            self.drain()?;
+        }
+
+        Ok(())
+    }
+
+    /// writes a Huffman code of `length` bits, from msb to lsb.
+    pub fn write_code(&mut self, code: u32, length: u8) -> Result<()> {
+        debug_assert!(length > 0 && length <= 16);
+
+        let reversed = code.reverse_bits() >> (32 - u32::from(length));
+        self.write_bits(reversed, length)
+    }
+
+    /// pads the current byte with zero bits so that the next bit written starts
+    /// a new byte; does nothing when already on the byte boundary.
+    pub fn align_to_byte(&mut self) -> Result<()> {
+        if self.pos > 0 {
+            self.write_bits(0, 8 - self.pos)?;
+        }
+        Ok(())
+    }
+
+    /// writes `data` as is.
+    /// Note that this function must be called on the byte boundary.
+    pub fn write_bytes(&mut self, data: &[u8]) -> Result<()> {
+        assert_eq!(self.pos, 0, "write_bytes() called off the byte boundary");
+
+        self.drain()?;
+        self.writer.write_all(data)?;
+        self.written += data.len() as u64 * 8;
+
+        Ok(())
+    }
+
+    /// number of bits written so far, including pending ones.
+    pub fn bit_position(&self) -> u64 {
+        self.written + u64::from(self.pos)
+    }
+
+    /// pushes every complete byte to the underlying writer and flushes it.
+    ///
+    /// Bits that don't fill a byte yet are kept so that the bit stream
+    /// continues seamlessly; call `align_to_byte()` first to flush them too.
+    pub fn flush(&mut self) -> Result<()> {
+        self.drain()?;
+        self.writer.flush()
+    }
+
+    /// pads to the byte boundary, flushes, and returns the underlying writer.
+    pub fn finish(mut self) -> Result<W> {
+        self.align_to_byte()?;
+        self.flush()?;
+        Ok(self.writer)
+    }
+
+    #[allow(unused)]
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    fn drain(&mut self) -> Result<()> {
+        self.writer.write_all(&self.buf)?;
+        self.buf.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader::Reader;
+
+    fn reader(buf: &[u8]) -> Reader<&[u8]> {
+        Reader::new(buf)
+    }
+
+    fn read_bits(reader: &mut Reader<&[u8]>, bits: u8) -> u32 {
+        let mut ret = 0;
+        for i in 0..bits {
+            if reader.next_bit().unwrap() {
+                ret |= 1 << i;
+            }
+        }
+        ret
+    }
+
+    #[test]
+    #[allow(clippy::unusual_byte_groupings)]
+    fn bits_are_packed_from_lsb() {
+        let mut writer = BitWriter::new(vec![]);
+        writer.write_bits(0b1, 1).unwrap();
+        writer.write_bits(0b10, 2).unwrap();
+        writer.write_bits(0b11111, 5).unwrap();
+        writer.write_bits(0b101, 3).unwrap();
+        assert_eq!(writer.bit_position(), 11);
+
+        let buf = writer.finish().unwrap();
+        assert_eq!(buf, &[0b11111_10_1, 0b00000_101]);
+    }
+
+    #[test]
+    fn codes_are_packed_from_msb() {
+        let mut writer = BitWriter::new(vec![]);
+        writer.write_code(0b110, 3).unwrap();
+        writer.write_code(0b00110000, 8).unwrap();
+        let buf = writer.finish().unwrap();
+
+        // the decoder sees the bits of each code in the order msb to lsb
+        let mut reader = reader(&buf);
+        let bits: Vec<bool> = (0..11).map(|_| reader.next_bit().unwrap()).collect();
+        let expected = [1, 1, 0, 0, 0, 1, 1, 0, 0, 0, 0].map(|b| b == 1);
+        assert_eq!(bits, expected);
+    }
+
+    #[test]
+    fn reader_reads_back_what_was_written() {
+        let values: Vec<(u32, u8)> = (1..=32u8)
+            .map(|bits| (0xdeadbeefu32 >> (32 - bits), bits))
+            .collect();
+
+        let mut writer = BitWriter::new(vec![]);
+        for &(value, bits) in &values {
+            writer.write_bits(value, bits).unwrap();
+        }
+        writer.align_to_byte().unwrap();
+        writer.write_bytes(&[0xab, 0xcd]).unwrap();
+        let total_bits = writer.bit_position();
+        let buf = writer.finish().unwrap();
+        assert_eq!(total_bits, buf.len() as u64 * 8);
+
+        let mut reader = reader(&buf);
+        for &(value, bits) in &values {
+            assert_eq!(read_bits(&mut reader, bits), value);
+        }
+        assert_eq!(reader.next_byte().unwrap(), 0xab);
+        assert_eq!(reader.next_byte().unwrap(), 0xcd);
+    }
+
+    #[test]
+    fn flush_keeps_pending_bits() {
+        let mut writer = BitWriter::new(vec![]);
+        writer.write_bits(0x1ff, 9).unwrap();
+        writer.flush().unwrap();
+        assert_eq!(writer.get_mut(), &[0xff]);
+        assert_eq!(writer.bit_position(), 9);
+
+        writer.write_bits(0, 7).unwrap();
+        writer.flush().unwrap();
+        assert_eq!(writer.get_mut(), &[0xff, 0x01]);
+    }
+}
@@ -0,0 +1,888 @@
+mod huffman;
+mod lz77;
+mod optimal;
+mod parallel;
+mod raw;
+
+use crate::bit_writer::BitWriter;
+use crate::crc32::Crc32;
+use crate::dictionary::Dictionary;
+use crate::header::{encode_subfields, GzipFlags};
+use crate::{CompressOptions, CompressionLevel, Strategy};
+use anyhow::Result;
+use encoding_rs::mem::encode_latin1_lossy;
+use lz77::{MatchFinder, MatchParams, Matches, Parse, Token};
+use std::io::{Read, Write};
+
+// OS field of the header; see RFC 1952 section 2.3.1
+const OS: u8 = if cfg!(unix) {
+    3
+} else if cfg!(windows) {
+    11
+} else {
+    255
+};
+
+// amount of input to gather into a block
+const BLOCK_SIZE: usize = 65536;
+
+// reads up to `length` bytes, retrying on short reads; returns fewer bytes only
+// when `reader` reaches EOF.
+pub(crate) fn read_chunk<R>(reader: &mut R, length: usize) -> Result<Vec<u8>>
+where
+    R: Read,
+{
+    let mut buf = Vec::with_capacity(length);
+    reader.take(length as u64).read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+// (good_length, max_lazy, nice_length, max_chain) for each level, taken from
+// zlib's `configuration_table`
+#[rustfmt::skip]
+const LEVEL_PARAMS: [(usize, usize, usize, usize); 10] = [
+    (0, 0, 0, 0),
+    // 1..=3 use greedy matching
+    (4, 4, 8, 4), (4, 5, 16, 8), (4, 6, 32, 32),
+    // 4..=9 use lazy matching
+    (4, 4, 16, 16), (8, 16, 32, 32), (8, 16, 128, 128), (8, 32, 128, 256),
+    (32, 128, 258, 1024), (32, 258, 258, 4096),
+];
+
+// hash chain entries to examine for optimal parsing; same as Zopfli
+const ULTRA_MAX_CHAIN: usize = 8192;
+
+fn match_params(opts: &CompressOptions) -> MatchParams {
+    if opts.ultra.is_some() {
+        return MatchParams {
+            max_chain: ULTRA_MAX_CHAIN,
+            nice_length: lz77::MAX_MATCH,
+            ..Default::default()
+        };
+    }
+
+    let strategy = opts.strategy;
+    let level = opts.level.level();
+    let (good_length, max_lazy, nice_length, max_chain) = LEVEL_PARAMS[usize::from(level)];
+
+    let parse = match strategy {
+        _ if level == 0 => Parse::Literals,
+        Strategy::HuffmanOnly => Parse::Literals,
+        Strategy::Rle => Parse::Rle,
+        _ if level <= 3 => Parse::Greedy,
+        _ => Parse::Lazy,
+    };
+
+    MatchParams {
+        good_length,
+        max_lazy,
+        nice_length,
+        max_chain,
+        parse,
+        // zlib's `deflate_fast` ignores Z_FILTERED; only lazy matching
+        // rejects short matches
+        filtered: strategy == Strategy::Filtered && parse == Parse::Lazy,
+    }
+}
+
+// whether zlib would record the output as made by its fastest algorithm, in
+// gzip's XFL as well as zlib's FLEVEL; ultra is left to the callers
+pub(crate) fn is_fastest(opts: &CompressOptions) -> bool {
+    match opts.strategy {
+        Strategy::HuffmanOnly | Strategy::Rle | Strategy::Fixed => true,
+        Strategy::Default | Strategy::Filtered => opts.level.level() < 2,
+    }
+}
+
+// XFL field of the header, set the way zlib does
+fn extra_flags(opts: &CompressOptions) -> u8 {
+    match opts.level {
+        _ if opts.ultra.is_some() => 2,
+        CompressionLevel::BEST => 2,
+        _ if is_fastest(opts) => 4,
+        _ => 0,
+    }
+}
+
+/// DEFLATE encoder turning chunks of input into blocks.
+///
+/// The match window persists across blocks so that matches can refer to
+/// earlier chunks.
+pub struct Deflater {
+    finder: MatchFinder,
+    tokens: Vec<Token>,
+    // only used for optimal parsing
+    matches: Matches,
+    level: CompressionLevel,
+    strategy: Strategy,
+    ultra: Option<u32>,
+}
+
+impl Deflater {
+    pub fn new(opts: &CompressOptions) -> Self {
+        Self {
+            finder: MatchFinder::new(match_params(opts)),
+            tokens: vec![],
+            matches: Matches::new(),
+            level: opts.level,
+            strategy: opts.strategy,
+            ultra: opts.ultra,
+        }
+    }
+
+    /// primes the match window so that the first block can refer to
+    /// `dictionary`, e.g. the data preceding a chunk compressed in parallel.
+    pub fn set_dictionary(&mut self, dictionary: &[u8]) {
+        self.finder.set_dictionary(dictionary);
+    }
+
+    /// writes an empty stored block so that the output ends on the byte
+    /// boundary (zlib's `Z_SYNC_FLUSH`).
+    pub fn sync_flush<W>(&mut self, writer: &mut BitWriter<W>) -> Result<()>
+    where
+        W: Write,
+    {
+        raw::compress(writer, &[], false)
+    }
+
+    // writes `data` as whichever allowed block type turns out to be the
+    // smallest
+    pub fn compress_block<W>(
+        &mut self,
+        writer: &mut BitWriter<W>,
+        data: &[u8],
+        final_block: bool,
+    ) -> Result<()>
+    where
+        W: Write,
+    {
+        self.tokens.clear();
+        if let Some(iterations) = self.ultra {
+            self.finder.find_matches(data, &mut self.matches);
+            optimal::tokenize(data, &self.matches, iterations, &mut self.tokens);
+        } else if self.level == CompressionLevel::NONE {
+            return raw::compress(writer, data, final_block);
+        } else {
+            self.finder.tokenize(data, &mut self.tokens);
+        }
+        let tokens = &self.tokens;
+
+        let stored_cost = raw::cost(data, writer.bit_position());
+        let fixed_cost = huffman::fixed_cost(tokens);
+
+        if self.strategy == Strategy::Fixed && self.ultra.is_none() {
+            return if stored_cost <= fixed_cost {
+                raw::compress(writer, data, final_block)
+            } else {
+                huffman::compress_fixed(writer, tokens, final_block)
+            };
+        }
+
+        let dynamic = huffman::DynamicBlock::new(tokens);
+        let dynamic_cost = dynamic.cost(tokens);
+
+        if stored_cost <= fixed_cost && stored_cost <= dynamic_cost {
+            raw::compress(writer, data, final_block)
+        } else if fixed_cost <= dynamic_cost {
+            huffman::compress_fixed(writer, tokens, final_block)
+        } else {
+            dynamic.write(writer, tokens, final_block)
+        }
+    }
+}
+
+pub(crate) fn write_header<W>(
+    writer: &mut W,
+    name: Option<&str>,
+    mtime: u32,
+    opts: &CompressOptions,
+) -> Result<()>
+where
+    W: Write,
+{
+    let mut flags = 0x00;
+    if name.is_some() {
+        flags |= GzipFlags::NAME;
+    }
+    if opts.header_crc {
+        flags |= GzipFlags::HCRC;
+    }
+    if !opts.extra_subfields.is_empty() {
+        flags |= GzipFlags::EXTRA;
+    }
+
+    // the header is assembled first for FHCRC
+    let mut header = vec![];
+    // ID1, ID2, CM
+    header.extend_from_slice(&[0x1f, 0x8b, 0x08, flags]);
+    header.extend_from_slice(&mtime.to_le_bytes());
+    // XFL, OS
+    header.extend_from_slice(&[extra_flags(opts), OS]);
+
+    if !opts.extra_subfields.is_empty() {
+        let extra = encode_subfields(&opts.extra_subfields)?;
+        // XLEN
+        header.extend_from_slice(&(extra.len() as u16).to_le_bytes());
+        header.extend_from_slice(&extra);
+    }
+
+    if let Some(name) = name {
+        // the name must be ISO 8859-1 (LATIN-1) and zero-terminated
+        let name = encode_latin1_lossy(name);
+        header.extend_from_slice(&name);
+        header.push(0);
+    }
+
+    if opts.header_crc {
+        // the lower 16 bits of CRC-32 of the header
+        let mut crc = Crc32::new();
+        crc.update(&header);
+        header.extend_from_slice(&(crc.sum() as u16).to_le_bytes());
+    }
+
+    writer.write_all(&header)?;
+
+    Ok(())
+}
+
+// compresses everything `reader` yields into a DEFLATE stream on the calling
+// thread, with matches allowed to refer to `dictionary`; returns (CRC-32,
+// length) of the input.
+fn compress_serial<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    dictionary: &[u8],
+    opts: &CompressOptions,
+) -> Result<(u32, usize)>
+where
+    R: Read,
+    W: Write,
+{
+    let mut bit_writer = BitWriter::new(writer);
+    let mut deflater = Deflater::new(opts);
+    deflater.set_dictionary(dictionary);
+    let mut crc = Crc32::new();
+    let mut total_bytes = 0usize;
+
+    // we need to know whether a block is the last one before writing it, so
+    // always read one chunk ahead.
+    let mut current = read_chunk(reader, BLOCK_SIZE)?;
+    loop {
+        let next = read_chunk(reader, BLOCK_SIZE)?;
+        let final_block = next.is_empty();
+
+        deflater.compress_block(&mut bit_writer, &current, final_block)?;
+        crc.update(&current);
+        total_bytes = total_bytes.wrapping_add(current.len());
+
+        if final_block {
+            break;
+        }
+        current = next;
+    }
+
+    // the trailer starts on the byte boundary
+    bit_writer.finish()?;
+
+    Ok((crc.sum(), total_bytes))
+}
+
+/// compresses everything `reader` yields into a DEFLATE stream, on multiple
+/// threads if asked to; returns (CRC-32, length) of the input.
+///
+/// Matches may refer to `dictionary` as if it preceded the input.
+pub(crate) fn deflate<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    dictionary: &[u8],
+    opts: &CompressOptions,
+) -> Result<(u32, usize)>
+where
+    R: Read,
+    W: Write,
+{
+    if opts.threads > 1 {
+        parallel::compress(reader, writer, dictionary, opts.threads, opts)
+    } else {
+        compress_serial(reader, writer, dictionary, opts)
+    }
+}
+
+/// compresses everything `reader` yields into a single gzip member.
+///
+/// `name` and `mtime` go into FNAME and MTIME of the header respectively.
+pub fn compress<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    name: Option<&str>,
+    mtime: u32,
+    opts: &CompressOptions,
+) -> Result<()>
+where
+    R: Read,
+    W: Write,
+{
+    write_header(writer, name, mtime, opts)?;
+
+    let (crc, total_bytes) = deflate(reader, writer, &[], opts)?;
+
+    // CRC32, ISIZE
+    writer.write_all(&crc.to_le_bytes())?;
+    writer.write_all(&((total_bytes & 0xffffffff) as u32).to_le_bytes())?;
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// compresses everything `reader` yields into a raw DEFLATE stream, without
+/// any header or trailer; returns the number of bytes read.
+///
+/// When `dictionary` is given, matches may refer to it; the decompressor then
+/// needs the same dictionary.
+pub fn deflate_raw<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    dictionary: Option<&Dictionary>,
+    opts: &CompressOptions,
+) -> Result<u64>
+where
+    R: Read,
+    W: Write,
+{
+    let dictionary = dictionary.map_or(&[][..], |d| d.as_bytes());
+    let (_, total_bytes) = deflate(reader, writer, dictionary, opts)?;
+    writer.flush()?;
+    Ok(total_bytes as u64)
+}
+
+/// Compressor into a raw DEFLATE stream that implements `Write`.
+///
+/// Input is buffered up to a block and compressed as it fills, so memory use
+/// stays bounded. `flush()` emits everything written so far followed by a sync
+/// flush; call `finish()` to write the final block. `threads` of the options
+/// is ignored.
+pub struct RawEncoder<W>
+where
+    W: Write,
+{
+    // None once finished
+    writer: Option<BitWriter<W>>,
+    deflater: Deflater,
+    buf: Vec<u8>,
+}
+
+impl<W> RawEncoder<W>
+where
+    W: Write,
+{
+    pub fn new(writer: W, opts: &CompressOptions) -> Self {
+        Self {
+            writer: Some(BitWriter::new(writer)),
+            deflater: Deflater::new(opts),
+            buf: Vec::with_capacity(BLOCK_SIZE),
+        }
+    }
+
+    /// matches may refer to `dictionary` as if it preceded the input
+    pub fn with_dictionary(writer: W, opts: &CompressOptions, dictionary: &Dictionary) -> Self {
+        let mut encoder = Self::new(writer, opts);
+        encoder.deflater.set_dictionary(dictionary.as_bytes());
+        encoder
+    }
+
+    // compresses buffered input
+    fn compress_buffered(&mut self, final_block: bool) -> Result<()> {
+        let writer = self.writer.as_mut().unwrap();
+        self.deflater
+            .compress_block(writer, &self.buf, final_block)?;
+        self.buf.clear();
+        Ok(())
+    }
+
+    /// writes the final block and returns the inner writer
+    pub fn finish(mut self) -> std::io::Result<W> {
+        self.compress_buffered(true).map_err(crate::into_io_error)?;
+        self.writer.take().unwrap().finish()
+    }
+}
+
+impl<W> Write for RawEncoder<W>
+where
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let amount = std::cmp::min(buf.len(), BLOCK_SIZE - self.buf.len());
+        self.buf.extend_from_slice(&buf[..amount]);
+
+        if self.buf.len() == BLOCK_SIZE {
+            self.compress_buffered(false)
+                .map_err(crate::into_io_error)?;
+        }
+
+        Ok(amount)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if !self.buf.is_empty() {
+            self.compress_buffered(false)
+                .map_err(crate::into_io_error)?;
+        }
+        let writer = self.writer.as_mut().unwrap();
+        self.deflater
+            .sync_flush(writer)
+            .map_err(crate::into_io_error)?;
+        writer.flush()
+    }
+}
+
+/// Compressor into a gzip stream that implements `Write`.
+///
+/// Works the same way as `RawEncoder` with a header and a trailer around the
+/// DEFLATE stream; call `finish()` to write the final block and the trailer.
+pub struct GzEncoder<W>
+where
+    W: Write,
+{
+    inner: RawEncoder<W>,
+    // the header is written just before anything else so that `new` can't
+    // fail; None once written
+    opts: Option<CompressOptions>,
+    crc: Crc32,
+    total_bytes: usize,
+}
+
+impl<W> GzEncoder<W>
+where
+    W: Write,
+{
+    /// the header has neither FNAME nor MTIME
+    pub fn new(writer: W, opts: CompressOptions) -> Self {
+        Self {
+            inner: RawEncoder::new(writer, &opts),
+            opts: Some(opts),
+            crc: Crc32::new(),
+            total_bytes: 0,
+        }
+    }
+
+    fn write_header_once(&mut self) -> std::io::Result<()> {
+        if let Some(opts) = self.opts.take() {
+            let mut header = vec![];
+            write_header(&mut header, None, 0, &opts).map_err(crate::into_io_error)?;
+            // nothing has been compressed yet, so it's on the byte boundary
+            self.inner.writer.as_mut().unwrap().write_bytes(&header)?;
+        }
+        Ok(())
+    }
+
+    /// writes the final block and the trailer, and returns the inner writer
+    pub fn finish(mut self) -> std::io::Result<W> {
+        self.write_header_once()?;
+        let mut writer = self.inner.finish()?;
+
+        // CRC32, ISIZE
+        writer.write_all(&self.crc.sum().to_le_bytes())?;
+        writer.write_all(&((self.total_bytes & 0xffffffff) as u32).to_le_bytes())?;
+        writer.flush()?;
+
+        Ok(writer)
+    }
+}
+
+impl<W> Write for GzEncoder<W>
+where
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.write_header_once()?;
+
+        let amount = self.inner.write(buf)?;
+        self.crc.update(&buf[..amount]);
+        self.total_bytes = self.total_bytes.wrapping_add(amount);
+
+        Ok(amount)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.write_header_once()?;
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::sample;
+    use crate::{DecompressOptions, ExtraSubfield};
+
+    fn compress_with(data: &[u8], opts: &CompressOptions) -> Vec<u8> {
+        let mut compressed = vec![];
+        compress(&mut &data[..], &mut compressed, Some("data.bin"), 0, opts).unwrap();
+        compressed
+    }
+
+    fn decompress(compressed: &[u8]) -> Vec<u8> {
+        let mut decompressed = vec![];
+        crate::decompress::decompress(
+            &mut &compressed[..],
+            &mut decompressed,
+            &DecompressOptions {
+                show_header: false,
+                no_emit: false,
+            },
+        )
+        .unwrap();
+
+        decompressed
+    }
+
+    fn roundtrip(data: &[u8]) -> Vec<u8> {
+        decompress(&compress_with(data, &CompressOptions::default()))
+    }
+
+    #[test]
+    fn empty_input_roundtrips() {
+        assert_eq!(roundtrip(&[]), &[]);
+    }
+
+    #[test]
+    fn input_spanning_multiple_blocks_roundtrips() {
+        let data: Vec<u8> = (0..200_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        assert_eq!(roundtrip(&data), data);
+    }
+
+    #[test]
+    fn repetitive_input_shrinks() {
+        let data: Vec<u8> = b"hello, world! "
+            .iter()
+            .cycle()
+            .take(100_000)
+            .copied()
+            .collect();
+        let mut compressed = vec![];
+        compress(
+            &mut &data[..],
+            &mut compressed,
+            None,
+            0,
+            &CompressOptions::default(),
+        )
+        .unwrap();
+
+        assert!(compressed.len() < 1000);
+        assert_eq!(roundtrip(&data), data);
+    }
+
+    #[test]
+    fn every_level_and_strategy_roundtrips() {
+        let data = sample(5000);
+        let strategies = [
+            Strategy::Default,
+            Strategy::Filtered,
+            Strategy::HuffmanOnly,
+            Strategy::Rle,
+            Strategy::Fixed,
+        ];
+
+        for level in 0..=9 {
+            for strategy in strategies {
+                let opts = CompressOptions {
+                    level: CompressionLevel::new(level).unwrap(),
+                    strategy,
+                    ..Default::default()
+                };
+                let compressed = compress_with(&data, &opts);
+                assert_eq!(decompress(&compressed), data, "{} {:?}", level, strategy);
+            }
+        }
+    }
+
+    #[test]
+    fn higher_levels_compress_better() {
+        let data = sample(5000);
+        let size = |level| {
+            let opts = CompressOptions {
+                level: CompressionLevel::new(level).unwrap(),
+                ..Default::default()
+            };
+            compress_with(&data, &opts).len()
+        };
+
+        assert!(size(0) > data.len());
+        assert!(size(1) < size(0));
+        assert!(size(9) <= size(6));
+        assert!(size(6) < size(1));
+    }
+
+    #[test]
+    fn ultra_compresses_best() {
+        // optimal parsing is slow, especially in debug builds
+        let data = &sample(5000)[..16384];
+        let best = CompressOptions {
+            level: CompressionLevel::BEST,
+            ..Default::default()
+        };
+        let ultra = CompressOptions {
+            ultra: Some(5),
+            ..Default::default()
+        };
+
+        let compressed = compress_with(data, &ultra);
+        assert!(compressed.len() < compress_with(data, &best).len());
+        assert_eq!(compressed, compress_with(data, &ultra));
+        assert_eq!(decompress(&compressed), data);
+        // XFL
+        assert_eq!(compressed[8], 2);
+    }
+
+    #[test]
+    fn ultra_handles_empty_input_and_flush() {
+        let ultra = CompressOptions {
+            ultra: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(decompress(&compress_with(&[], &ultra)), &[]);
+
+        let threads = CompressOptions {
+            threads: 2,
+            ..ultra.clone()
+        };
+        assert_eq!(decompress(&compress_with(&[], &threads)), &[]);
+
+        // `finish` after `flush` compresses an empty final block
+        let data = &sample(5000)[..1000];
+        let mut encoder = GzEncoder::new(vec![], ultra);
+        encoder.write_all(data).unwrap();
+        encoder.flush().unwrap();
+        let compressed = encoder.finish().unwrap();
+        assert_eq!(decompress(&compressed), data);
+    }
+
+    #[test]
+    fn parallel_output_is_independent_of_threads() {
+        // long enough to make several chunks
+        let data: Vec<u8> = sample(5000).iter().cycle().take(600_000).copied().collect();
+        let with_threads = |threads| {
+            let opts = CompressOptions {
+                threads,
+                ..Default::default()
+            };
+            compress_with(&data, &opts)
+        };
+
+        let compressed = with_threads(2);
+        assert_eq!(decompress(&compressed), data);
+        assert_eq!(compressed, with_threads(3));
+        assert_eq!(compressed, with_threads(8));
+        // priming chunks with the preceding data keeps the loss small
+        assert!(compressed.len() < with_threads(1).len() * 21 / 20);
+    }
+
+    #[test]
+    fn parallel_handles_short_input() {
+        let opts = CompressOptions {
+            threads: 4,
+            ..Default::default()
+        };
+        for data in [&b""[..], b"a", &sample(5000)[..1000]] {
+            assert_eq!(decompress(&compress_with(data, &opts)), data);
+        }
+    }
+
+    #[test]
+    fn extra_flags_reflect_level() {
+        let xfl = |level, strategy| {
+            let opts = CompressOptions {
+                level,
+                strategy,
+                ..Default::default()
+            };
+            compress_with(b"", &opts)[8]
+        };
+
+        assert_eq!(xfl(CompressionLevel::BEST, Strategy::Default), 2);
+        assert_eq!(xfl(CompressionLevel::FASTEST, Strategy::Default), 4);
+        assert_eq!(xfl(CompressionLevel::default(), Strategy::Default), 0);
+        assert_eq!(xfl(CompressionLevel::default(), Strategy::HuffmanOnly), 4);
+        assert_eq!(xfl(CompressionLevel::default(), Strategy::Fixed), 4);
+    }
+
+    #[test]
+    fn filtered_only_affects_lazy_levels() {
+        let filtered = |level| {
+            match_params(&CompressOptions {
+                level,
+                strategy: Strategy::Filtered,
+                ..Default::default()
+            })
+            .filtered
+        };
+
+        assert!(!filtered(CompressionLevel::FASTEST));
+        assert!(filtered(CompressionLevel::default()));
+        assert!(filtered(CompressionLevel::BEST));
+    }
+
+    #[test]
+    fn header_carries_name_and_mtime() {
+        let mut compressed = vec![];
+        compress(
+            &mut &b"hello"[..],
+            &mut compressed,
+            Some("hello.txt"),
+            0x12345678,
+            &CompressOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(&compressed[..4], &[0x1f, 0x8b, 0x08, 0x08]);
+        assert_eq!(&compressed[4..8], &0x12345678u32.to_le_bytes());
+        assert_eq!(&compressed[10..20], b"hello.txt\0");
+    }
+
+    #[test]
+    fn extra_subfields_are_written() {
+        let subfields = vec![
+            ExtraSubfield {
+                id: *b"XY",
+                data: b"hello".to_vec(),
+            },
+            ExtraSubfield {
+                id: *b"AB",
+                data: vec![],
+            },
+        ];
+        let opts = CompressOptions {
+            extra_subfields: subfields.clone(),
+            header_crc: true,
+            ..Default::default()
+        };
+        let compressed = compress_with(b"hello", &opts);
+
+        // FEXTRA, FNAME and FHCRC
+        assert_eq!(compressed[3], 0x0e);
+        let headers = crate::decompress::decompress(
+            &mut &compressed[..],
+            &mut std::io::sink(),
+            &DecompressOptions {
+                show_header: false,
+                no_emit: true,
+            },
+        )
+        .unwrap();
+        assert_eq!(headers[0].extra_subfields, subfields);
+        assert_eq!(headers[0].name.as_deref(), Some("data.bin"));
+    }
+
+    #[test]
+    fn header_crc_is_written_and_verified() {
+        let opts = CompressOptions {
+            header_crc: true,
+            ..Default::default()
+        };
+        let mut compressed = compress_with(b"hello", &opts);
+
+        // FHCRC and FNAME
+        assert_eq!(compressed[3], 0x0a);
+        assert_eq!(decompress(&compressed), b"hello");
+
+        // corrupt the file name
+        compressed[12] ^= 0x01;
+        let err = crate::decompress::decompress(
+            &mut &compressed[..],
+            &mut vec![],
+            &DecompressOptions {
+                show_header: false,
+                no_emit: false,
+            },
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("header CRC mismatch"), "{}", err);
+    }
+
+    #[test]
+    fn encoder_matches_one_shot_compression() {
+        let data = sample(5000);
+        let opts = CompressOptions {
+            no_name: true,
+            ..Default::default()
+        };
+
+        // small writes must not change block boundaries
+        let mut encoder = GzEncoder::new(vec![], CompressOptions::default());
+        for piece in data.chunks(1000) {
+            encoder.write_all(piece).unwrap();
+        }
+        let streamed = encoder.finish().unwrap();
+
+        let mut one_shot = vec![];
+        compress(&mut &data[..], &mut one_shot, None, 0, &opts).unwrap();
+
+        assert_eq!(streamed, one_shot);
+    }
+
+    #[test]
+    fn raw_encoder_matches_deflate_raw() {
+        let data = sample(5000);
+
+        let mut encoder = RawEncoder::new(vec![], &CompressOptions::default());
+        for piece in data.chunks(777) {
+            encoder.write_all(piece).unwrap();
+        }
+        let streamed = encoder.finish().unwrap();
+
+        let mut one_shot = vec![];
+        let consumed =
+            deflate_raw(&mut &data[..], &mut one_shot, None, &Default::default()).unwrap();
+        assert_eq!(consumed, data.len() as u64);
+        assert_eq!(streamed, one_shot);
+
+        let mut decompressed = vec![];
+        crate::inflate_raw(&mut &streamed[..], &mut decompressed, None).unwrap();
+        assert_eq!(decompressed, data);
+    }
+
+    #[test]
+    fn encoder_flush_emits_decodable_prefix() {
+        let data = sample(5000);
+        let (first, second) = data.split_at(12345);
+
+        let mut encoder = GzEncoder::new(vec![], CompressOptions::default());
+        encoder.write_all(first).unwrap();
+        encoder.flush().unwrap();
+        let flushed_len = encoder.inner.writer.as_mut().unwrap().get_mut().len();
+        assert!(flushed_len > 10);
+        encoder.write_all(second).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        assert_eq!(decompress(&compressed), data);
+    }
+
+    #[test]
+    fn dictionary_primes_match_window() {
+        let data = sample(1000);
+        let dictionary = Dictionary::new(&data[..4000]);
+        let message = &data[2000..2300];
+
+        let mut without = vec![];
+        deflate_raw(&mut &message[..], &mut without, None, &Default::default()).unwrap();
+        let mut with = vec![];
+        deflate_raw(
+            &mut &message[..],
+            &mut with,
+            Some(&dictionary),
+            &Default::default(),
+        )
+        .unwrap();
+        assert!(with.len() < without.len());
+
+        let mut encoder = RawEncoder::with_dictionary(vec![], &Default::default(), &dictionary);
+        encoder.write_all(message).unwrap();
+        assert_eq!(encoder.finish().unwrap(), with);
+
+        let mut decompressed = vec![];
+        crate::inflate_raw(&mut &with[..], &mut decompressed, Some(&dictionary)).unwrap();
+        assert_eq!(decompressed, message);
+    }
+}
@@ -0,0 +1,504 @@
+use super::lz77::Token;
+use crate::bit_writer::BitWriter;
+use crate::decompress::huffman::{
+    ALPHABET_ORDER, DIST_INFO, DIST_LENGTHS, LENGTH_INFO, LIT_LENGTHS,
+};
+use anyhow::Result;
+use std::io::Write;
+
+const END_OF_BLOCK: usize = 256;
+
+// maximum code lengths allowed by RFC 1951
+pub const MAX_BITS: u8 = 15;
+const MAX_CODE_LENGTH_BITS: u8 = 7;
+
+// builds code lengths from symbol frequencies such that no code is longer than
+// `max_bits`. Symbols of frequency 0 get no code, but at least two symbols
+// always get one so that every decoder is happy with the resulting code.
+pub fn build_lengths(freqs: &[u32], max_bits: u8) -> Vec<u8> {
+    let mut lengths = vec![0; freqs.len()];
+
+    let mut symbols: Vec<usize> = (0..freqs.len()).filter(|&i| freqs[i] > 0).collect();
+    let missing = 2usize.saturating_sub(symbols.len());
+    symbols.extend((0..freqs.len()).filter(|&i| freqs[i] == 0).take(missing));
+    // ties are broken by symbol to keep the output deterministic
+    symbols.sort_by_key(|&i| (freqs[i], i));
+
+    if symbols.len() < 2 {
+        for &i in &symbols {
+            lengths[i] = 1;
+        }
+        return lengths;
+    }
+
+    // plain Huffman construction with two queues: the sorted leaves and the
+    // internal nodes, which are created in nondecreasing order of weight
+    let n = symbols.len();
+    let mut weights: Vec<u64> = symbols.iter().map(|&i| freqs[i].max(1).into()).collect();
+    let mut parents = vec![0usize; 2 * n - 1];
+    let (mut leaf, mut node) = (0, n);
+    for next in n..2 * n - 1 {
+        let mut pick = || {
+            if leaf < n && (node >= next || weights[leaf] <= weights[node]) {
+                leaf += 1;
+                leaf - 1
+            } else {
+                node += 1;
+                node - 1
+            }
+        };
+        let (a, b) = (pick(), pick());
+        weights.push(weights[a] + weights[b]);
+        parents[a] = next;
+        parents[b] = next;
+    }
+
+    // depth of each node; the root is the last one
+    let mut depths = vec![0usize; 2 * n - 1];
+    for i in (0..2 * n - 2).rev() {
+        depths[i] = depths[parents[i]] + 1;
+    }
+
+    // count codes per length, folding anything too long into `max_bits`
+    let max_bits = usize::from(max_bits);
+    let mut counts = vec![0usize; max_bits + 1];
+    for &depth in &depths[..n] {
+        counts[std::cmp::min(depth, max_bits)] += 1;
+    }
+
+    // folding breaks the Kraft inequality; restore it by moving leaves down
+    // from shorter lengths (as miniz and zlib do)
+    let kraft =
+        |counts: &[usize]| -> usize { (1..=max_bits).map(|l| counts[l] << (max_bits - l)).sum() };
+    while kraft(&counts) > 1 << max_bits {
+        counts[max_bits] -= 1;
+        for l in (1..max_bits).rev() {
+            if counts[l] > 0 {
+                counts[l] -= 1;
+                counts[l + 1] += 2;
+                break;
+            }
+        }
+    }
+
+    // the least frequent symbols get the longest codes
+    let mut sorted = symbols.iter();
+    for l in (1..=max_bits).rev() {
+        for _ in 0..counts[l] {
+            lengths[*sorted.next().unwrap()] = l as u8;
+        }
+    }
+
+    lengths
+}
+
+// assigns canonical codes to `lengths` in the same way `build_tree` does on
+// the decoding side (RFC 1951 section 3.2.2)
+pub fn build_codes(lengths: &[u8]) -> Vec<u16> {
+    const BITS_UPPER_BOUND: usize = 16;
+
+    let mut counts = [0u16; BITS_UPPER_BOUND];
+    for &l in lengths {
+        counts[usize::from(l)] += 1;
+    }
+    counts[0] = 0;
+
+    let mut next_code = [0u16; BITS_UPPER_BOUND];
+    for bits in 1..BITS_UPPER_BOUND {
+        next_code[bits] = (next_code[bits - 1] + counts[bits - 1]) << 1;
+    }
+
+    lengths
+        .iter()
+        .map(|&l| {
+            if l == 0 {
+                return 0;
+            }
+            let code = next_code[usize::from(l)];
+            next_code[usize::from(l)] += 1;
+            code
+        })
+        .collect()
+}
+
+// returns (index into `LENGTH_INFO`, value of the extra bits)
+pub fn length_symbol(length: usize) -> (usize, u32) {
+    debug_assert!((3..=258).contains(&length));
+
+    let index = LENGTH_INFO.partition_point(|&(_, base)| base <= length) - 1;
+    (index, (length - LENGTH_INFO[index].1) as u32)
+}
+
+// returns (index into `DIST_INFO`, value of the extra bits)
+pub fn dist_symbol(distance: usize) -> (usize, u32) {
+    debug_assert!((1..=32768).contains(&distance));
+
+    let index = DIST_INFO.partition_point(|&(_, base)| base <= distance) - 1;
+    (index, (distance - DIST_INFO[index].1) as u32)
+}
+
+/// A Huffman code for an alphabet: code lengths and the codes derived from them.
+pub struct Codes {
+    pub lengths: Vec<u8>,
+    pub codes: Vec<u16>,
+}
+
+impl Codes {
+    pub fn new(lengths: &[u8]) -> Self {
+        Self {
+            lengths: lengths.to_vec(),
+            codes: build_codes(lengths),
+        }
+    }
+
+    fn write<W>(&self, writer: &mut BitWriter<W>, symbol: usize) -> Result<()>
+    where
+        W: Write,
+    {
+        debug_assert!(self.lengths[symbol] > 0, "symbol {} has no code", symbol);
+
+        writer.write_code(self.codes[symbol].into(), self.lengths[symbol])?;
+        Ok(())
+    }
+}
+
+// number of bits `write_compressed_data` would write
+pub fn data_cost(tokens: &[Token], lit_lengths: &[u8], dist_lengths: &[u8]) -> u64 {
+    let mut bits = u64::from(lit_lengths[END_OF_BLOCK]);
+
+    for &token in tokens {
+        bits += match token {
+            Token::Literal(b) => lit_lengths[usize::from(b)].into(),
+            Token::Match { length, distance } => {
+                let (length_index, _) = length_symbol(length.into());
+                let (dist_index, _) = dist_symbol(distance.into());
+                u64::from(lit_lengths[length_index + 257])
+                    + u64::from(LENGTH_INFO[length_index].0)
+                    + u64::from(dist_lengths[dist_index])
+                    + u64::from(DIST_INFO[dist_index].0)
+            }
+        };
+    }
+
+    bits
+}
+
+// counterpart of `read_compressed_data`; writes `tokens` followed by the end of
+// block code
+pub fn write_compressed_data<W>(
+    writer: &mut BitWriter<W>,
+    tokens: &[Token],
+    lit_codes: &Codes,
+    dist_codes: &Codes,
+) -> Result<()>
+where
+    W: Write,
+{
+    for &token in tokens {
+        match token {
+            Token::Literal(b) => lit_codes.write(writer, b.into())?,
+            Token::Match { length, distance } => {
+                let (index, extra) = length_symbol(length.into());
+                lit_codes.write(writer, index + 257)?;
+                writer.write_bits(extra, LENGTH_INFO[index].0)?;
+
+                let (index, extra) = dist_symbol(distance.into());
+                dist_codes.write(writer, index)?;
+                writer.write_bits(extra, DIST_INFO[index].0)?;
+            }
+        }
+    }
+
+    lit_codes.write(writer, END_OF_BLOCK)?;
+
+    Ok(())
+}
+
+thread_local!(
+    static LIT_CODES: Codes = Codes::new(&LIT_LENGTHS);
+    static DIST_CODES: Codes = Codes::new(&DIST_LENGTHS);
+);
+
+// number of bits `compress_fixed` would write
+pub fn fixed_cost(tokens: &[Token]) -> u64 {
+    3 + data_cost(tokens, &LIT_LENGTHS, &DIST_LENGTHS)
+}
+
+pub fn compress_fixed<W>(
+    writer: &mut BitWriter<W>,
+    tokens: &[Token],
+    final_block: bool,
+) -> Result<()>
+where
+    W: Write,
+{
+    // BFINAL, BTYPE = 01
+    writer.write_bits(final_block.into(), 1)?;
+    writer.write_bits(0b01, 2)?;
+
+    LIT_CODES.with(|lit_codes| {
+        DIST_CODES.with(|dist_codes| write_compressed_data(writer, tokens, lit_codes, dist_codes))
+    })
+}
+
+// run-length encodes code lengths using symbols 16, 17 and 18; returns
+// (symbol, value of the extra bits) pairs
+fn encode_code_lengths(lengths: &[u8]) -> Vec<(u8, u8)> {
+    let mut encoded = vec![];
+
+    let mut i = 0;
+    while i < lengths.len() {
+        let value = lengths[i];
+        let run = lengths[i..].iter().take_while(|&&l| l == value).count();
+
+        if value == 0 && run >= 3 {
+            let run = std::cmp::min(run, 138);
+            if run <= 10 {
+                encoded.push((17, (run - 3) as u8));
+            } else {
+                encoded.push((18, (run - 11) as u8));
+            }
+            i += run;
+        } else if value != 0 && run >= 4 {
+            // the value itself must precede symbol 16
+            encoded.push((value, 0));
+            let mut remain = run - 1;
+            while remain >= 3 {
+                let repeat = std::cmp::min(remain, 6);
+                encoded.push((16, (repeat - 3) as u8));
+                remain -= repeat;
+            }
+            for _ in 0..remain {
+                encoded.push((value, 0));
+            }
+            i += run;
+        } else {
+            encoded.push((value, 0));
+            i += 1;
+        }
+    }
+
+    encoded
+}
+
+fn code_length_extra_bits(symbol: u8) -> u8 {
+    match symbol {
+        16 => 2,
+        17 => 3,
+        18 => 7,
+        _ => 0,
+    }
+}
+
+/// Codes for a dynamic Huffman block, along with the encoded header
+/// describing them.
+pub struct DynamicBlock {
+    lit_codes: Codes,
+    dist_codes: Codes,
+    code_length_codes: Codes,
+    code_lengths: Vec<(u8, u8)>,
+    hlit: usize,
+    hdist: usize,
+    hclen: usize,
+}
+
+impl DynamicBlock {
+    pub fn new(tokens: &[Token]) -> Self {
+        let mut lit_freqs = [0u32; 286];
+        let mut dist_freqs = [0u32; 30];
+        for &token in tokens {
+            match token {
+                Token::Literal(b) => lit_freqs[usize::from(b)] += 1,
+                Token::Match { length, distance } => {
+                    lit_freqs[length_symbol(length.into()).0 + 257] += 1;
+                    dist_freqs[dist_symbol(distance.into()).0] += 1;
+                }
+            }
+        }
+        lit_freqs[END_OF_BLOCK] = 1;
+
+        Self::from_lengths(
+            &build_lengths(&lit_freqs, MAX_BITS),
+            &build_lengths(&dist_freqs, MAX_BITS),
+        )
+    }
+
+    // `lit_lengths` must cover at least symbols 0..=256 and `dist_lengths` at
+    // least one symbol
+    pub fn from_lengths(lit_lengths: &[u8], dist_lengths: &[u8]) -> Self {
+        // trailing zero lengths need not be sent
+        let hlit = std::cmp::max(
+            257,
+            lit_lengths
+                .iter()
+                .rposition(|&l| l > 0)
+                .map_or(0, |i| i + 1),
+        );
+        let hdist = std::cmp::max(
+            1,
+            dist_lengths
+                .iter()
+                .rposition(|&l| l > 0)
+                .map_or(0, |i| i + 1),
+        );
+
+        let mut all_lengths = lit_lengths[..hlit].to_vec();
+        all_lengths.extend_from_slice(&dist_lengths[..hdist]);
+        let code_lengths = encode_code_lengths(&all_lengths);
+
+        let mut freqs = [0u32; 19];
+        for &(symbol, _) in &code_lengths {
+            freqs[usize::from(symbol)] += 1;
+        }
+        let code_length_lengths = build_lengths(&freqs, MAX_CODE_LENGTH_BITS);
+
+        let hclen = std::cmp::max(
+            4,
+            ALPHABET_ORDER
+                .iter()
+                .rposition(|&i| code_length_lengths[i] > 0)
+                .map_or(0, |i| i + 1),
+        );
+
+        Self {
+            lit_codes: Codes::new(&lit_lengths[..hlit]),
+            dist_codes: Codes::new(&dist_lengths[..hdist]),
+            code_length_codes: Codes::new(&code_length_lengths),
+            code_lengths,
+            hlit,
+            hdist,
+            hclen,
+        }
+    }
+
+    fn header_cost(&self) -> u64 {
+        let lengths = &self.code_length_codes.lengths;
+        let encoded: u64 = self
+            .code_lengths
+            .iter()
+            .map(|&(symbol, _)| {
+                u64::from(lengths[usize::from(symbol)] + code_length_extra_bits(symbol))
+            })
+            .sum();
+
+        3 + 5 + 5 + 4 + 3 * self.hclen as u64 + encoded
+    }
+
+    // number of bits `write` would write
+    pub fn cost(&self, tokens: &[Token]) -> u64 {
+        self.header_cost() + data_cost(tokens, &self.lit_codes.lengths, &self.dist_codes.lengths)
+    }
+
+    // counterpart of `decompress_dynamic`
+    pub fn write<W>(
+        &self,
+        writer: &mut BitWriter<W>,
+        tokens: &[Token],
+        final_block: bool,
+    ) -> Result<()>
+    where
+        W: Write,
+    {
+        // BFINAL, BTYPE = 10
+        writer.write_bits(final_block.into(), 1)?;
+        writer.write_bits(0b10, 2)?;
+
+        writer.write_bits((self.hlit - 257) as u32, 5)?;
+        writer.write_bits((self.hdist - 1) as u32, 5)?;
+        writer.write_bits((self.hclen - 4) as u32, 4)?;
+
+        for &i in ALPHABET_ORDER.iter().take(self.hclen) {
+            writer.write_bits(self.code_length_codes.lengths[i].into(), 3)?;
+        }
+
+        for &(symbol, extra) in &self.code_lengths {
+            self.code_length_codes.write(writer, symbol.into())?;
+            writer.write_bits(extra.into(), code_length_extra_bits(symbol))?;
+        }
+
+        write_compressed_data(writer, tokens, &self.lit_codes, &self.dist_codes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_match_rfc_example() {
+        // RFC 1951 section 3.2.2: lengths (3, 3, 3, 3, 3, 2, 4, 4) for A..H
+        let codes = build_codes(&[3, 3, 3, 3, 3, 2, 4, 4]);
+        assert_eq!(
+            codes,
+            [0b010, 0b011, 0b100, 0b101, 0b110, 0b00, 0b1110, 0b1111]
+        );
+    }
+
+    fn kraft_sum(lengths: &[u8], max_bits: u8) -> usize {
+        lengths
+            .iter()
+            .filter(|&&l| l > 0)
+            .map(|&l| 1 << (max_bits - l))
+            .sum()
+    }
+
+    #[test]
+    fn lengths_follow_frequencies() {
+        let lengths = build_lengths(&[10, 1, 1, 0, 2], MAX_BITS);
+        assert_eq!(lengths, [1, 3, 3, 0, 2]);
+    }
+
+    #[test]
+    fn at_least_two_codes_are_built() {
+        assert_eq!(build_lengths(&[0, 0, 0], MAX_BITS), [1, 1, 0]);
+        assert_eq!(build_lengths(&[0, 0, 5], MAX_BITS), [1, 0, 1]);
+    }
+
+    #[test]
+    fn lengths_are_limited() {
+        // Fibonacci frequencies yield the deepest possible Huffman tree
+        let mut freqs = vec![1u32, 1];
+        while freqs.len() < 30 {
+            let n = freqs.len();
+            freqs.push(freqs[n - 1] + freqs[n - 2]);
+        }
+
+        for max_bits in [7, MAX_BITS] {
+            let lengths = build_lengths(&freqs, max_bits);
+            assert!(lengths.iter().all(|&l| l > 0 && l <= max_bits));
+            // the code must be complete, otherwise bits are wasted
+            assert_eq!(kraft_sum(&lengths, max_bits), 1 << max_bits);
+        }
+    }
+
+    #[test]
+    fn code_lengths_are_run_length_encoded() {
+        let lengths = [0, 0, 0, 0, 5, 5, 5, 5, 5, 5, 5, 5, 3, 3];
+        assert_eq!(
+            encode_code_lengths(&lengths),
+            [(17, 1), (5, 0), (16, 3), (5, 0), (3, 0), (3, 0)]
+        );
+
+        let lengths = [0; 150];
+        assert_eq!(encode_code_lengths(&lengths), [(18, 127), (18, 1)]);
+    }
+
+    #[test]
+    fn symbols_cover_whole_domain() {
+        for length in 3..=258 {
+            let (index, extra) = length_symbol(length);
+            let (bits, base) = LENGTH_INFO[index];
+            assert_eq!(base + extra as usize, length);
+            assert!(extra < 1 << bits);
+        }
+        // 258 has its own code even though 284 could encode it
+        assert_eq!(length_symbol(258), (28, 0));
+
+        for distance in 1..=32768 {
+            let (index, extra) = dist_symbol(distance);
+            let (bits, base) = DIST_INFO[index];
+            assert_eq!(base + extra as usize, distance);
+            assert!(extra < 1 << bits);
+        }
+    }
+}
@@ -0,0 +1,577 @@
+// LZ77 match finder over a sliding window, modelled after zlib's hash chains.
+
+// maximum distance is 32768
+pub const WINDOW_SIZE: usize = 32768;
+pub const MIN_MATCH: usize = 3;
+pub const MAX_MATCH: usize = 258;
+
+const HASH_BITS: u32 = 15;
+const HASH_SIZE: usize = 1 << HASH_BITS;
+
+// matches of minimum length are worth less than two literals when they are
+// that far; see `TOO_FAR` in zlib
+const TOO_FAR: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Token {
+    Literal(u8),
+    // length in 3..=258, distance in 1..=32768
+    Match { length: u16, distance: u16 },
+}
+
+/// parameters tuning the speed/ratio trade-off; names follow zlib.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MatchParams {
+    /// cut the chain search to a quarter once a match at least this long was
+    /// found
+    pub good_length: usize,
+    /// lazy matching: don't look for a better match once the current one is
+    /// at least this long.
+    /// greedy matching: only insert the strings inside a match into the hash
+    /// table when the match is at most this long.
+    pub max_lazy: usize,
+    /// stop searching once a match at least this long was found
+    pub nice_length: usize,
+    /// maximum number of hash chain entries to examine
+    pub max_chain: usize,
+    pub parse: Parse,
+    /// reject matches of 5 bytes or less, which rarely pay off for data like
+    /// filtered images (zlib's `Z_FILTERED`)
+    pub filtered: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parse {
+    /// take the longest match at each position
+    Greedy,
+    /// defer a match by one byte when the next one is longer
+    Lazy,
+    /// only look for runs of the same byte, i.e. matches of distance 1
+    Rle,
+    /// emit literals only
+    Literals,
+}
+
+impl Default for MatchParams {
+    // zlib's level 6
+    fn default() -> Self {
+        Self {
+            good_length: 8,
+            max_lazy: 16,
+            nice_length: 128,
+            max_chain: 128,
+            parse: Parse::Lazy,
+            filtered: false,
+        }
+    }
+}
+
+/// Matches available at each position, as found by `find_matches()`.
+///
+/// The matches at a position are (length, distance) pairs in increasing
+/// order of both; each pair stands for every length above the preceding one
+/// up to its own, all available at its distance.
+#[derive(Debug, Default)]
+pub struct Matches {
+    starts: Vec<usize>,
+    steps: Vec<(u16, u16)>,
+}
+
+impl Matches {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    // number of positions covered
+    pub fn len(&self) -> usize {
+        self.starts.len().saturating_sub(1)
+    }
+
+    pub fn at(&self, i: usize) -> &[(u16, u16)] {
+        &self.steps[self.starts[i]..self.starts[i + 1]]
+    }
+}
+
+pub struct MatchFinder {
+    params: MatchParams,
+    // history followed by the data being tokenized; `window[0]` lies at
+    // absolute position `base` of the whole input
+    window: Vec<u8>,
+    base: usize,
+    // most recent absolute position + 1 for each hash value; 0 means none
+    head: Vec<usize>,
+    // previous absolute position + 1 with the same hash, indexed by position
+    // modulo `WINDOW_SIZE`
+    prev: Vec<usize>,
+}
+
+fn hash(bytes: &[u8]) -> usize {
+    let n = u32::from(bytes[0]) | u32::from(bytes[1]) << 8 | u32::from(bytes[2]) << 16;
+    (n.wrapping_mul(0x9e3779b1) >> (32 - HASH_BITS)) as usize
+}
+
+impl MatchFinder {
+    pub fn new(params: MatchParams) -> Self {
+        Self {
+            params,
+            window: Vec::with_capacity(WINDOW_SIZE * 2),
+            base: 0,
+            head: vec![0; HASH_SIZE],
+            prev: vec![0; WINDOW_SIZE],
+        }
+    }
+
+    // registers the string starting at `window[i]`; returns the previous head
+    // of its chain
+    fn insert(&mut self, i: usize) -> usize {
+        debug_assert!(i + MIN_MATCH <= self.window.len());
+
+        let h = hash(&self.window[i..]);
+        let pos = self.base + i;
+        let prev_head = self.head[h];
+        self.prev[pos % WINDOW_SIZE] = prev_head;
+        self.head[h] = pos + 1;
+        prev_head
+    }
+
+    // searches the chain starting at `chain` (absolute position + 1) for the
+    // longest match for `window[i..end]`; returns (length, distance) where
+    // length is 0 when nothing better than `prev_length` was found.
+    fn longest_match(
+        &self,
+        i: usize,
+        end: usize,
+        chain: usize,
+        prev_length: usize,
+    ) -> (usize, usize) {
+        let mut chain_length = self.params.max_chain;
+        if prev_length >= self.params.good_length {
+            chain_length /= 4;
+        }
+
+        let (best_length, best_distance) = self.search(
+            i,
+            end,
+            chain,
+            prev_length,
+            chain_length,
+            self.params.nice_length,
+            |_, _| {},
+        );
+
+        if best_distance == 0
+            || (best_length == MIN_MATCH && best_distance > TOO_FAR)
+            || (self.params.filtered && best_length <= 5)
+        {
+            (0, 0)
+        } else {
+            (best_length, best_distance)
+        }
+    }
+
+    // walks at most `chain_length` entries of the chain starting at `chain`,
+    // calling `record` with (length, distance) each time a match longer than
+    // the ones before is found; returns the last one recorded, or distance 0
+    // when none was.
+    #[allow(clippy::too_many_arguments)]
+    fn search<F>(
+        &self,
+        i: usize,
+        end: usize,
+        chain: usize,
+        prev_length: usize,
+        mut chain_length: usize,
+        nice_length: usize,
+        mut record: F,
+    ) -> (usize, usize)
+    where
+        F: FnMut(usize, usize),
+    {
+        let max_length = std::cmp::min(MAX_MATCH, end - i);
+        if max_length < MIN_MATCH {
+            return (0, 0);
+        }
+
+        let pos = self.base + i;
+        let limit = pos.saturating_sub(WINDOW_SIZE);
+        let nice_length = std::cmp::min(nice_length, max_length);
+
+        let mut best_length = prev_length;
+        let mut best_distance = 0;
+        let mut candidate = chain;
+        let target = &self.window[i..i + max_length];
+
+        while candidate > limit && chain_length > 0 {
+            let cand_pos = candidate - 1;
+            // positions at or after `pos` are stale entries of the chain
+            if cand_pos >= pos || cand_pos < self.base {
+                break;
+            }
+            let j = cand_pos - self.base;
+
+            // quick reject: the byte that would extend the best match must agree
+            if best_length < max_length && self.window[j + best_length] == target[best_length] {
+                let length = target
+                    .iter()
+                    .zip(&self.window[j..j + max_length])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+
+                if length > best_length {
+                    best_length = length;
+                    best_distance = pos - cand_pos;
+                    record(best_length, best_distance);
+                    if length >= nice_length {
+                        break;
+                    }
+                }
+            }
+
+            let next = self.prev[cand_pos % WINDOW_SIZE];
+            // chains must go backwards; anything else was overwritten
+            if next >= candidate {
+                break;
+            }
+            candidate = next;
+            chain_length -= 1;
+        }
+
+        (best_length, best_distance)
+    }
+
+    // appends `data` to the window; returns the range it occupies
+    fn append(&mut self, data: &[u8]) -> (usize, usize) {
+        self.slide();
+
+        let start = self.window.len();
+        self.window.extend_from_slice(data);
+        let end = self.window.len();
+
+        // strings at the end of the previous data couldn't be hashed until now
+        for i in start.saturating_sub(MIN_MATCH - 1)..start {
+            if i + MIN_MATCH <= end {
+                self.insert(i);
+            }
+        }
+
+        (start, end)
+    }
+
+    /// appends `data` to the window and pushes the tokens representing it to
+    /// `tokens`. Matches may refer to data given in preceding calls, up to
+    /// `WINDOW_SIZE` bytes back, but never extend past the end of `data`.
+    pub fn tokenize(&mut self, data: &[u8], tokens: &mut Vec<Token>) {
+        let (start, end) = self.append(data);
+
+        match self.params.parse {
+            Parse::Greedy => self.tokenize_greedy(start, end, tokens),
+            Parse::Lazy => self.tokenize_lazy(start, end, tokens),
+            Parse::Rle => self.tokenize_rle(start, end, tokens),
+            Parse::Literals => tokens.extend(data.iter().map(|&b| Token::Literal(b))),
+        }
+    }
+
+    /// appends `dictionary` to the window without producing tokens, so that
+    /// matches in subsequent data can refer to it. Only the last
+    /// `WINDOW_SIZE` bytes of it are of use.
+    pub fn set_dictionary(&mut self, dictionary: &[u8]) {
+        let dictionary = &dictionary[dictionary.len().saturating_sub(WINDOW_SIZE)..];
+        let (start, end) = self.append(dictionary);
+
+        for i in start..end.saturating_sub(MIN_MATCH - 1) {
+            self.insert(i);
+        }
+    }
+
+    /// appends `data` to the window like `tokenize()`, but records every
+    /// useful match instead of choosing tokens: for each position of `data`
+    /// and each match length, the shortest distance at which it's found
+    /// within `max_chain` entries of the hash chain.
+    pub fn find_matches(&mut self, data: &[u8], matches: &mut Matches) {
+        let (start, end) = self.append(data);
+
+        matches.starts.clear();
+        matches.steps.clear();
+
+        for i in start..end {
+            matches.starts.push(matches.steps.len());
+            if i + MIN_MATCH <= end {
+                let chain = self.insert(i);
+                let steps = &mut matches.steps;
+                self.search(
+                    i,
+                    end,
+                    chain,
+                    MIN_MATCH - 1,
+                    self.params.max_chain,
+                    MAX_MATCH,
+                    |length, distance| steps.push((length as u16, distance as u16)),
+                );
+            }
+        }
+        matches.starts.push(matches.steps.len());
+    }
+
+    fn tokenize_rle(&self, start: usize, end: usize, tokens: &mut Vec<Token>) {
+        let mut i = start;
+        while i < end {
+            if i > 0 {
+                let prev = self.window[i - 1];
+                let run = self.window[i..end]
+                    .iter()
+                    .take(MAX_MATCH)
+                    .take_while(|&&b| b == prev)
+                    .count();
+
+                if run >= MIN_MATCH {
+                    tokens.push(Token::Match {
+                        length: run as u16,
+                        distance: 1,
+                    });
+                    i += run;
+                    continue;
+                }
+            }
+
+            tokens.push(Token::Literal(self.window[i]));
+            i += 1;
+        }
+    }
+
+    fn tokenize_greedy(&mut self, start: usize, end: usize, tokens: &mut Vec<Token>) {
+        let mut i = start;
+        while i < end {
+            let (length, distance) = if i + MIN_MATCH <= end {
+                let chain = self.insert(i);
+                self.longest_match(i, end, chain, MIN_MATCH - 1)
+            } else {
+                (0, 0)
+            };
+
+            if length >= MIN_MATCH {
+                tokens.push(Token::Match {
+                    length: length as u16,
+                    distance: distance as u16,
+                });
+                if length <= self.params.max_lazy {
+                    for j in i + 1..i + length {
+                        if j + MIN_MATCH <= end {
+                            self.insert(j);
+                        }
+                    }
+                }
+                i += length;
+            } else {
+                tokens.push(Token::Literal(self.window[i]));
+                i += 1;
+            }
+        }
+    }
+
+    fn tokenize_lazy(&mut self, start: usize, end: usize, tokens: &mut Vec<Token>) {
+        // match found at `i - 1`, if any
+        let mut prev_length = 0;
+        let mut prev_distance = 0;
+        // whether the byte at `i - 1` is yet to be emitted
+        let mut pending = false;
+
+        let mut i = start;
+        while i < end {
+            let (length, distance) = if i + MIN_MATCH <= end {
+                let chain = self.insert(i);
+                if prev_length < self.params.max_lazy {
+                    self.longest_match(i, end, chain, std::cmp::max(prev_length, MIN_MATCH - 1))
+                } else {
+                    (0, 0)
+                }
+            } else {
+                (0, 0)
+            };
+
+            if prev_length >= MIN_MATCH && length <= prev_length {
+                // the match at `i - 1` wins; `i` has been inserted already
+                tokens.push(Token::Match {
+                    length: prev_length as u16,
+                    distance: prev_distance as u16,
+                });
+                let next = i - 1 + prev_length;
+                for j in i + 1..next {
+                    if j + MIN_MATCH <= end {
+                        self.insert(j);
+                    }
+                }
+                i = next;
+                prev_length = 0;
+                pending = false;
+            } else {
+                if pending {
+                    tokens.push(Token::Literal(self.window[i - 1]));
+                }
+                pending = true;
+                prev_length = length;
+                prev_distance = distance;
+                i += 1;
+            }
+        }
+
+        if pending {
+            tokens.push(Token::Literal(self.window[end - 1]));
+        }
+    }
+
+    // drops history no longer reachable so that the window doesn't grow
+    // unboundedly
+    fn slide(&mut self) {
+        if self.window.len() > WINDOW_SIZE {
+            let excess = self.window.len() - WINDOW_SIZE;
+            self.window.drain(..excess);
+            self.base += excess;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // lines from a small set, so that matches cover most of the data
+    fn repetitive_text() -> Vec<u8> {
+        let mut data = vec![];
+        for i in 0..20000u32 {
+            data.extend_from_slice(format!("line {} of {}\n", i % 337, i % 7).as_bytes());
+        }
+        data
+    }
+
+    // reconstructs the input from tokens the way the decoder does
+    fn expand(tokens: &[Token]) -> Vec<u8> {
+        let mut out = vec![];
+        for &token in tokens {
+            match token {
+                Token::Literal(b) => out.push(b),
+                Token::Match { length, distance } => {
+                    assert!((MIN_MATCH..=MAX_MATCH).contains(&length.into()));
+                    assert!((1..=WINDOW_SIZE).contains(&distance.into()));
+                    let start = out.len() - usize::from(distance);
+                    for k in 0..usize::from(length) {
+                        out.push(out[start + k]);
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn repetition_is_found() {
+        let mut finder = MatchFinder::new(MatchParams::default());
+        let mut tokens = vec![];
+        finder.tokenize(b"abcabcabcabcabcabc", &mut tokens);
+
+        assert_eq!(
+            tokens,
+            [
+                Token::Literal(b'a'),
+                Token::Literal(b'b'),
+                Token::Literal(b'c'),
+                Token::Match {
+                    length: 15,
+                    distance: 3
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn tokens_expand_to_input() {
+        let data = repetitive_text();
+        for parse in [Parse::Greedy, Parse::Lazy] {
+            let params = MatchParams {
+                parse,
+                ..Default::default()
+            };
+            let mut finder = MatchFinder::new(params);
+            let mut tokens = vec![];
+            finder.tokenize(&data, &mut tokens);
+
+            assert_eq!(expand(&tokens), data);
+            assert!(tokens.len() < data.len() / 4);
+        }
+    }
+
+    #[test]
+    fn rle_emits_distance_1_only() {
+        let params = MatchParams {
+            parse: Parse::Rle,
+            ..Default::default()
+        };
+        let mut finder = MatchFinder::new(params);
+        let mut tokens = vec![];
+        finder.tokenize(b"abababaaaaaaaab", &mut tokens);
+        finder.tokenize(b"bbbb", &mut tokens);
+
+        assert_eq!(expand(&tokens), b"abababaaaaaaaabbbbb");
+        assert!(tokens
+            .iter()
+            .all(|t| !matches!(t, Token::Match { distance, .. } if *distance != 1)));
+        assert_eq!(
+            tokens[tokens.len() - 1],
+            Token::Match {
+                length: 4,
+                distance: 1
+            }
+        );
+    }
+
+    #[test]
+    fn find_matches_records_shortest_distance_per_length() {
+        let mut finder = MatchFinder::new(MatchParams::default());
+        let mut matches = Matches::new();
+        finder.find_matches(b"abcdXabcYabcdZabcd", &mut matches);
+
+        assert_eq!(matches.len(), 18);
+        assert_eq!(matches.at(0), []);
+        // "abc" is found 4 bytes back, "abcd" only 9 bytes back
+        assert_eq!(matches.at(9), [(3, 4), (4, 9)]);
+        assert_eq!(matches.at(14), [(4, 5)]);
+    }
+
+    #[test]
+    fn matches_reach_into_dictionary() {
+        let mut finder = MatchFinder::new(MatchParams::default());
+        finder.set_dictionary(b"hello, world");
+        let mut tokens = vec![];
+        finder.tokenize(b"world, hello", &mut tokens);
+
+        assert_eq!(
+            tokens,
+            [
+                Token::Match {
+                    length: 5,
+                    distance: 5
+                },
+                Token::Literal(b','),
+                Token::Literal(b' '),
+                Token::Match {
+                    length: 5,
+                    distance: 19
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn matches_reach_into_previous_calls() {
+        let data = repetitive_text();
+        let mut finder = MatchFinder::new(MatchParams::default());
+        let mut tokens = vec![];
+        for chunk in data.chunks(10000) {
+            finder.tokenize(chunk, &mut tokens);
+        }
+
+        assert_eq!(expand(&tokens), data);
+        assert!(tokens.iter().all(
+            |t| !matches!(t, Token::Match { distance, .. } if usize::from(*distance) > WINDOW_SIZE)
+        ));
+    }
+}
@@ -0,0 +1,214 @@
+// Optimal parsing in the manner of Zopfli: tokens are chosen by a shortest
+// path search where the cost of each symbol comes from a Huffman cost model,
+// which in turn is refined from the statistics of the previous parse.
+
+use super::huffman::{dist_symbol, length_symbol, DynamicBlock};
+use super::lz77::{Matches, Token, MAX_MATCH, MIN_MATCH};
+use crate::decompress::huffman::{DIST_INFO, DIST_LENGTHS, LENGTH_INFO, LIT_LENGTHS};
+
+const END_OF_BLOCK: usize = 256;
+
+// estimated number of bits for each symbol of the literal/length and the
+// distance alphabets, including extra bits
+struct CostModel {
+    literals: [f64; 256],
+    lengths: [f64; MAX_MATCH + 1],
+    dist_symbols: [f64; 30],
+}
+
+impl CostModel {
+    fn new(lit_bits: &[f64], dist_bits: &[f64]) -> Self {
+        let mut literals = [0.0; 256];
+        literals.copy_from_slice(&lit_bits[..256]);
+
+        let mut lengths = [f64::INFINITY; MAX_MATCH + 1];
+        for (length, cost) in lengths.iter_mut().enumerate().skip(MIN_MATCH) {
+            let (index, _) = length_symbol(length);
+            *cost = lit_bits[index + 257] + f64::from(LENGTH_INFO[index].0);
+        }
+
+        let mut dist_symbols = [0.0; 30];
+        for (index, cost) in dist_symbols.iter_mut().enumerate() {
+            *cost = dist_bits[index] + f64::from(DIST_INFO[index].0);
+        }
+
+        Self {
+            literals,
+            lengths,
+            dist_symbols,
+        }
+    }
+
+    // costs of the fixed Huffman code, used before any statistics exist
+    fn fixed() -> Self {
+        let lit_bits: Vec<f64> = LIT_LENGTHS.iter().map(|&l| l.into()).collect();
+        let dist_bits: Vec<f64> = DIST_LENGTHS.iter().map(|&l| l.into()).collect();
+        Self::new(&lit_bits, &dist_bits)
+    }
+
+    // costs according to the entropy of the symbols in `tokens`; unused
+    // symbols are priced as if they occurred once
+    fn from_tokens(tokens: &[Token]) -> Self {
+        let mut lit_freqs = [0u32; 286];
+        let mut dist_freqs = [0u32; 30];
+        for &token in tokens {
+            match token {
+                Token::Literal(b) => lit_freqs[usize::from(b)] += 1,
+                Token::Match { length, distance } => {
+                    lit_freqs[length_symbol(length.into()).0 + 257] += 1;
+                    dist_freqs[dist_symbol(distance.into()).0] += 1;
+                }
+            }
+        }
+        lit_freqs[END_OF_BLOCK] = 1;
+
+        fn entropy(freqs: &[u32]) -> Vec<f64> {
+            let total: u32 = freqs.iter().sum();
+            let log_total = f64::from(std::cmp::max(total, 1)).log2();
+            freqs
+                .iter()
+                .map(|&f| {
+                    if f == 0 {
+                        log_total
+                    } else {
+                        log_total - f64::from(f).log2()
+                    }
+                })
+                .collect()
+        }
+
+        Self::new(&entropy(&lit_freqs), &entropy(&dist_freqs))
+    }
+
+    fn distance(&self, distance: usize) -> f64 {
+        self.dist_symbols[dist_symbol(distance).0]
+    }
+}
+
+// finds the cheapest sequence of tokens for `data` under `model`
+fn shortest_path(data: &[u8], matches: &Matches, model: &CostModel, tokens: &mut Vec<Token>) {
+    let n = data.len();
+    debug_assert_eq!(matches.len(), n);
+
+    // cheapest cost to reach each position, and the (length, distance) of the
+    // last token on the way there; length 1 stands for a literal
+    let mut costs = vec![f64::INFINITY; n + 1];
+    let mut choices = vec![(0u16, 0u16); n + 1];
+    costs[0] = 0.0;
+
+    for i in 0..n {
+        let cost = costs[i];
+
+        let literal = cost + model.literals[usize::from(data[i])];
+        if literal < costs[i + 1] {
+            costs[i + 1] = literal;
+            choices[i + 1] = (1, 0);
+        }
+
+        let mut length = MIN_MATCH;
+        for &(max_length, distance) in matches.at(i) {
+            let base = cost + model.distance(distance.into());
+            while length <= usize::from(max_length) {
+                let candidate = base + model.lengths[length];
+                if candidate < costs[i + length] {
+                    costs[i + length] = candidate;
+                    choices[i + length] = (length as u16, distance);
+                }
+                length += 1;
+            }
+        }
+    }
+
+    let start = tokens.len();
+    let mut i = n;
+    while i > 0 {
+        let (length, distance) = choices[i];
+        if length == 1 {
+            tokens.push(Token::Literal(data[i - 1]));
+        } else {
+            tokens.push(Token::Match { length, distance });
+        }
+        i -= usize::from(length);
+    }
+    tokens[start..].reverse();
+}
+
+/// pushes tokens for `data` to `tokens`, refining the cost model up to
+/// `iterations` times and keeping the parse that makes the smallest dynamic
+/// block. The result only depends on the input.
+pub fn tokenize(data: &[u8], matches: &Matches, iterations: u32, tokens: &mut Vec<Token>) {
+    // an empty parse would equal the initial `previous` and leave no best one
+    if data.is_empty() {
+        return;
+    }
+
+    let mut model = CostModel::fixed();
+    let mut best: Option<(u64, Vec<Token>)> = None;
+    let mut previous = vec![];
+
+    for _ in 0..std::cmp::max(iterations, 1) {
+        let mut current = vec![];
+        shortest_path(data, matches, &model, &mut current);
+
+        // the same parse would lead to the same model from here on
+        if current == previous {
+            break;
+        }
+
+        let cost = DynamicBlock::new(&current).cost(&current);
+        if best
+            .as_ref()
+            .map_or(true, |(best_cost, _)| cost < *best_cost)
+        {
+            best = Some((cost, current.clone()));
+        }
+
+        model = CostModel::from_tokens(&current);
+        previous = current;
+    }
+
+    tokens.extend(best.unwrap().1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::lz77::{MatchFinder, MatchParams};
+    use super::*;
+    use crate::test_util::sample;
+
+    fn parse(data: &[u8], iterations: u32) -> Vec<Token> {
+        let mut finder = MatchFinder::new(MatchParams::default());
+        let mut matches = Matches::new();
+        finder.find_matches(data, &mut matches);
+
+        let mut tokens = vec![];
+        tokenize(data, &matches, iterations, &mut tokens);
+        tokens
+    }
+
+    #[test]
+    fn parse_beats_lazy_matching() {
+        let data = sample(3000);
+
+        let mut finder = MatchFinder::new(MatchParams::default());
+        let mut lazy = vec![];
+        finder.tokenize(&data, &mut lazy);
+        let lazy_cost = DynamicBlock::new(&lazy).cost(&lazy);
+
+        let optimal = parse(&data, 10);
+        let optimal_cost = DynamicBlock::new(&optimal).cost(&optimal);
+
+        assert!(optimal_cost < lazy_cost);
+    }
+
+    #[test]
+    fn empty_input_makes_no_tokens() {
+        assert!(parse(&[], 3).is_empty());
+    }
+
+    #[test]
+    fn parse_is_deterministic() {
+        let data = sample(3000);
+        assert_eq!(parse(&data, 5), parse(&data, 5));
+    }
+}
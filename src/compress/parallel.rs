@@ -0,0 +1,131 @@
+// Parallel compression in the manner of pigz: input is split into chunks,
+// each compressed on its own thread with the end of the preceding chunk as a
+// dictionary, and the results are concatenated into a single DEFLATE stream.
+
+use super::{read_chunk, Deflater, BLOCK_SIZE};
+use crate::bit_writer::BitWriter;
+use crate::crc32::{crc32_combine, Crc32};
+use crate::CompressOptions;
+use anyhow::{anyhow, Result};
+use std::io::{Read, Write};
+use std::thread;
+
+// amount of input each thread handles at once; same as pigz
+const CHUNK_SIZE: usize = 131072;
+// maximum distance is 32768
+const DICTIONARY_SIZE: usize = 32768;
+
+struct CompressedChunk {
+    data: Vec<u8>,
+    crc: u32,
+    length: usize,
+}
+
+// compresses `chunk` as a part of a DEFLATE stream. Unless it's the last
+// chunk, the output is terminated by a sync flush so that it ends on the byte
+// boundary and the next chunk can be appended as is.
+fn compress_chunk(
+    chunk: &[u8],
+    dictionary: &[u8],
+    last: bool,
+    opts: &CompressOptions,
+) -> Result<CompressedChunk> {
+    let mut writer = BitWriter::new(Vec::with_capacity(chunk.len() / 2));
+    let mut deflater = Deflater::new(opts);
+    deflater.set_dictionary(dictionary);
+
+    let mut blocks = chunk.chunks(BLOCK_SIZE).peekable();
+    if blocks.peek().is_none() {
+        deflater.compress_block(&mut writer, &[], last)?;
+    }
+    while let Some(block) = blocks.next() {
+        deflater.compress_block(&mut writer, block, last && blocks.peek().is_none())?;
+    }
+    if !last {
+        deflater.sync_flush(&mut writer)?;
+    }
+
+    let mut crc = Crc32::new();
+    crc.update(chunk);
+
+    Ok(CompressedChunk {
+        data: writer.finish()?,
+        crc: crc.sum(),
+        length: chunk.len(),
+    })
+}
+
+/// compresses everything `reader` yields into a DEFLATE stream using
+/// `threads` threads, with matches allowed to refer to `dictionary`; returns
+/// (CRC-32, length) of the input.
+///
+/// The output doesn't depend on the number of threads.
+pub fn compress<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    dictionary: &[u8],
+    threads: usize,
+    opts: &CompressOptions,
+) -> Result<(u32, usize)>
+where
+    R: Read,
+    W: Write,
+{
+    debug_assert!(threads > 0);
+
+    let mut crc = 0;
+    let mut total_bytes = 0usize;
+    let mut dictionary = dictionary[dictionary.len().saturating_sub(DICTIONARY_SIZE)..].to_vec();
+
+    // we need to know whether a chunk is the last one before compressing it,
+    // so always read one chunk ahead.
+    let mut next = read_chunk(reader, CHUNK_SIZE)?;
+    let mut last = false;
+
+    while !last {
+        let mut batch = vec![];
+        while batch.len() < threads && !last {
+            let chunk = std::mem::replace(&mut next, read_chunk(reader, CHUNK_SIZE)?);
+            last = next.is_empty();
+            batch.push(chunk);
+        }
+
+        let results: Vec<Result<CompressedChunk>> = thread::scope(|s| {
+            let handles: Vec<_> = batch
+                .iter()
+                .enumerate()
+                .map(|(i, chunk)| {
+                    let dictionary = if i == 0 {
+                        &dictionary[..]
+                    } else {
+                        let prev = &batch[i - 1];
+                        &prev[prev.len().saturating_sub(DICTIONARY_SIZE)..]
+                    };
+                    let last = last && i == batch.len() - 1;
+                    s.spawn(move || compress_chunk(chunk, dictionary, last, opts))
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .map(|h| {
+                    h.join()
+                        .unwrap_or_else(|_| Err(anyhow!("compression thread panicked")))
+                })
+                .collect()
+        });
+
+        for result in results {
+            let chunk = result?;
+            writer.write_all(&chunk.data)?;
+            crc = crc32_combine(crc, chunk.crc, chunk.length as u64);
+            total_bytes = total_bytes.wrapping_add(chunk.length);
+        }
+
+        // chunks are never shorter than the dictionary except for the last one
+        let tail = batch.last().unwrap();
+        dictionary = tail[tail.len().saturating_sub(DICTIONARY_SIZE)..].to_vec();
+    }
+
+    Ok((crc, total_bytes))
+}
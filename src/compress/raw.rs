@@ -0,0 +1,51 @@
+use crate::bit_writer::BitWriter;
+use anyhow::Result;
+use std::convert::TryInto;
+use std::io::Write;
+
+// maximum number of bytes a stored block can hold; LEN is 16 bits wide
+pub const MAX_STORED_LENGTH: usize = 0xffff;
+
+// number of bits `compress` would write when `bit_position` bits have been
+// written so far
+pub fn cost(data: &[u8], bit_position: u64) -> u64 {
+    let blocks = std::cmp::max(1, data.len().div_ceil(MAX_STORED_LENGTH));
+    // the first header may need padding; the others start on the byte boundary
+    let padding = (8 - (bit_position + 3) % 8) % 8;
+    (blocks as u64 - 1) * 8 + 3 + padding + blocks as u64 * 32 + data.len() as u64 * 8
+}
+
+// writes `data` as stored blocks, splitting it as needed
+pub fn compress<W>(writer: &mut BitWriter<W>, data: &[u8], final_block: bool) -> Result<()>
+where
+    W: Write,
+{
+    let mut chunks = data.chunks(MAX_STORED_LENGTH).peekable();
+    if chunks.peek().is_none() {
+        return compress_one(writer, data, final_block);
+    }
+    while let Some(chunk) = chunks.next() {
+        compress_one(writer, chunk, final_block && chunks.peek().is_none())?;
+    }
+    Ok(())
+}
+
+fn compress_one<W>(writer: &mut BitWriter<W>, data: &[u8], final_block: bool) -> Result<()>
+where
+    W: Write,
+{
+    debug_assert!(data.len() <= MAX_STORED_LENGTH);
+
+    // BFINAL, BTYPE = 00
+    writer.write_bits(final_block.into(), 1)?;
+    writer.write_bits(0b00, 2)?;
+    // any remaining bits up to the next byte boundary are ignored
+    writer.align_to_byte()?;
+
+    let len: u16 = data.len().try_into().unwrap();
+    writer.write_bytes(&len.to_le_bytes())?;
+    writer.write_bytes(&(!len).to_le_bytes())?;
+    writer.write_bytes(data)?;
+
+    Ok(())
+}
@@ -0,0 +1,313 @@
+// CRC-32 as specified in RFC 1952 section 8 (ISO 3309 polynomial, reflected).
+// Long inputs go through carry-less multiplication where the CPU supports it,
+// and through slicing-by-8 tables otherwise.
+
+mod clmul;
+
+const POLYNOMIAL: u32 = 0xedb88320;
+
+const fn build_table() -> [u32; 256] {
+    let mut table = [0; 256];
+
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 > 0 {
+                POLYNOMIAL ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+
+    table
+}
+
+// TABLES[k][n] is the CRC of byte n followed by k zero bytes, which lets
+// `update` process 8 bytes at a time (slicing-by-8)
+const fn build_slicing_tables() -> [[u32; 256]; 8] {
+    let mut tables = [[0; 256]; 8];
+    tables[0] = build_table();
+
+    let mut k = 1;
+    while k < 8 {
+        let mut n = 0;
+        while n < 256 {
+            let prev = tables[k - 1][n];
+            tables[k][n] = tables[0][(prev & 0xff) as usize] ^ (prev >> 8);
+            n += 1;
+        }
+        k += 1;
+    }
+
+    tables
+}
+
+const TABLES: [[u32; 256]; 8] = build_slicing_tables();
+
+// multiplies polynomials `a` and `b` modulo the CRC polynomial; both are in
+// the reflected representation, i.e. x^0 is the msb
+const fn multiply_mod_p(a: u32, mut b: u32) -> u32 {
+    let mut m = 1 << 31;
+    let mut p = 0;
+    while m > 0 {
+        if a & m > 0 {
+            p ^= b;
+        }
+        m >>= 1;
+        b = if b & 1 > 0 {
+            (b >> 1) ^ POLYNOMIAL
+        } else {
+            b >> 1
+        };
+    }
+    p
+}
+
+// x^(2^n) modulo the CRC polynomial for each n
+const fn build_x2n_table() -> [u32; 32] {
+    let mut table = [0; 32];
+
+    // x^1
+    let mut p = 1 << 30;
+    table[0] = p;
+    let mut n = 1;
+    while n < 32 {
+        p = multiply_mod_p(p, p);
+        table[n] = p;
+        n += 1;
+    }
+
+    table
+}
+
+const X2N_TABLE: [u32; 32] = build_x2n_table();
+
+// x^(8 * `bytes`) modulo the CRC polynomial
+fn shift_operator(mut bytes: u64) -> u32 {
+    // x^0
+    let mut p = 1 << 31;
+    // multiplying by x^8 shifts by a byte
+    let mut k = 3;
+    while bytes > 0 {
+        if bytes & 1 > 0 {
+            p = multiply_mod_p(X2N_TABLE[k % 32], p);
+        }
+        bytes >>= 1;
+        k += 1;
+    }
+    p
+}
+
+/// returns the CRC-32 of the concatenation of two byte sequences, given the
+/// CRC-32 of each and the length of the second one.
+pub fn crc32_combine(crc1: u32, crc2: u32, len2: u64) -> u32 {
+    multiply_mod_p(shift_operator(len2), crc1) ^ crc2
+}
+
+/// running CRC-32 of the bytes passed to `update` so far.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Crc32(u32);
+
+impl Crc32 {
+    pub fn new() -> Self {
+        Self(0)
+    }
+
+    pub fn update(&mut self, buf: &[u8]) {
+        self.0 = !update(!self.0, buf);
+    }
+
+    pub fn sum(&self) -> u32 {
+        self.0
+    }
+}
+
+// picks the fastest implementation available at run time
+fn update(c: u32, buf: &[u8]) -> u32 {
+    if buf.len() < clmul::MIN_LENGTH {
+        return update_slicing_by_8(c, buf);
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("pclmulqdq") {
+        // SAFETY: PCLMULQDQ is detected right above and SSE2 is part of x86_64
+        let (c, rest) = unsafe { clmul::x86_64::update(c, buf) };
+        return update_slicing_by_8(c, rest);
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    if std::arch::is_aarch64_feature_detected!("pmull") {
+        // SAFETY: the feature is detected right above
+        let (c, rest) = unsafe { clmul::aarch64::update(c, buf) };
+        return update_slicing_by_8(c, rest);
+    }
+
+    update_slicing_by_8(c, buf)
+}
+
+fn update_bytewise(mut c: u32, buf: &[u8]) -> u32 {
+    for &b in buf {
+        c = TABLES[0][((c ^ u32::from(b)) & 0xff) as usize] ^ (c >> 8);
+    }
+    c
+}
+
+fn update_slicing_by_8(mut c: u32, buf: &[u8]) -> u32 {
+    let mut chunks = buf.chunks_exact(8);
+    for chunk in &mut chunks {
+        let lo = c ^ u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        let hi = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
+        c = TABLES[7][(lo & 0xff) as usize]
+            ^ TABLES[6][((lo >> 8) & 0xff) as usize]
+            ^ TABLES[5][((lo >> 16) & 0xff) as usize]
+            ^ TABLES[4][(lo >> 24) as usize]
+            ^ TABLES[3][(hi & 0xff) as usize]
+            ^ TABLES[2][((hi >> 8) & 0xff) as usize]
+            ^ TABLES[1][((hi >> 16) & 0xff) as usize]
+            ^ TABLES[0][(hi >> 24) as usize];
+    }
+    update_bytewise(c, chunks.remainder())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_input_yields_zero() {
+        assert_eq!(Crc32::new().sum(), 0);
+    }
+
+    #[test]
+    fn check_value() {
+        let mut crc = Crc32::new();
+        crc.update(b"123456789");
+        assert_eq!(crc.sum(), 0xcbf43926);
+    }
+
+    #[test]
+    fn combine_equals_crc_of_concatenation() {
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i * 31 % 255) as u8).collect();
+
+        let mut whole = Crc32::new();
+        whole.update(&data);
+
+        for split in [0, 1, 7, 8, 1000, 65536, data.len()] {
+            let (first, second) = data.split_at(split);
+            let mut crc1 = Crc32::new();
+            crc1.update(first);
+            let mut crc2 = Crc32::new();
+            crc2.update(second);
+
+            let combined = crc32_combine(crc1.sum(), crc2.sum(), second.len() as u64);
+            assert_eq!(combined, whole.sum(), "split at {}", split);
+        }
+    }
+
+    #[test]
+    fn slicing_by_8_matches_bytewise() {
+        let data: Vec<u8> = (0..1000u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+            .collect();
+
+        for start in 0..8 {
+            for len in [0, 1, 7, 8, 9, 15, 16, 17, 100, 990] {
+                let buf = &data[start..start + len];
+                assert_eq!(
+                    update_slicing_by_8(!0, buf),
+                    update_bytewise(!0, buf),
+                    "start = {}, len = {}",
+                    start,
+                    len,
+                );
+            }
+        }
+    }
+
+    // xorshift64; deterministic stand-in for random data
+    fn random_bytes(len: usize, seed: u64) -> Vec<u8> {
+        let mut x = seed;
+        (0..len)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 7;
+                x ^= x << 17;
+                x as u8
+            })
+            .collect()
+    }
+
+    // lengths around the thresholds of folding, plus some long ones
+    fn lengths() -> impl Iterator<Item = usize> {
+        (0..200).chain([255, 256, 257, 1000, 4096, 65536 + 15])
+    }
+
+    // carry-less product without any special instruction
+    fn portable_clmul(a: u64, b: u64) -> u128 {
+        (0..64)
+            .filter(|i| (b >> i) & 1 == 1)
+            .fold(0, |p, i| p ^ (u128::from(a) << i))
+    }
+
+    #[test]
+    fn folding_matches_table() {
+        for (seed, len) in lengths().enumerate() {
+            let buf = random_bytes(len + 3, seed as u64 + 1);
+            for start in 0..4 {
+                let buf = &buf[start..start + len];
+                let c = buf.first().map_or(!0, |&b| u32::from(b) * 0x01010101);
+                assert_eq!(update(c, buf), update_bytewise(c, buf), "len = {}", len);
+
+                // the path taken on CPUs without carry-less multiplication
+                // support, and the algorithm behind the PMULL one
+                if len >= clmul::MIN_LENGTH {
+                    let (folded, rest) = clmul::fold(c, buf, portable_clmul);
+                    assert_eq!(
+                        update_bytewise(folded, rest),
+                        update_bytewise(c, buf),
+                        "len = {}",
+                        len,
+                    );
+                }
+            }
+        }
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn pclmulqdq_matches_table() {
+        if !is_x86_feature_detected!("pclmulqdq") {
+            return;
+        }
+        for (seed, len) in lengths()
+            .filter(|&len| len >= clmul::MIN_LENGTH)
+            .enumerate()
+        {
+            let buf = random_bytes(len, !(seed as u64));
+            // SAFETY: PCLMULQDQ is detected at the top and SSE2 is part of
+            // x86_64
+            let (folded, rest) = unsafe { clmul::x86_64::update(!0, &buf) };
+            assert!(rest.len() < 16);
+            assert_eq!(update_bytewise(folded, rest), update_bytewise(!0, &buf));
+        }
+    }
+
+    #[test]
+    fn update_can_be_split() {
+        let mut whole = Crc32::new();
+        whole.update(b"The quick brown fox jumps over the lazy dog");
+
+        let mut split = Crc32::new();
+        split.update(b"The quick brown ");
+        split.update(b"fox jumps over the lazy dog");
+
+        assert_eq!(whole.sum(), 0x414fa339);
+        assert_eq!(split.sum(), whole.sum());
+    }
+}
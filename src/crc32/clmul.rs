@@ -0,0 +1,167 @@
+// CRC-32 by folding with carry-less multiplication, after Intel's "Fast CRC
+// Computation for Generic Polynomials Using PCLMULQDQ Instruction". Input is
+// taken 16 bytes at a time into 4 accumulators; multiplying one by x^n modulo
+// the polynomial moves it n bits forward, so it can be folded into the data n
+// bits ahead. What's left is reduced to 32 bits with Barrett reduction.
+//
+// Everything is bit-reflected like the CRC itself, so the constants are
+// reflected powers of x shifted left by one bit.
+
+// x^(4 * 128 + 32) and x^(4 * 128 - 32) modulo P; fold 64 bytes ahead
+const K1: u64 = 0x1_5444_2bd4;
+const K2: u64 = 0x1_c6e4_1596;
+// x^(128 + 32) and x^(128 - 32) modulo P; fold 16 bytes ahead
+const K3: u64 = 0x1_7519_97d0;
+const K4: u64 = 0x0_ccaa_009e;
+// x^64 modulo P
+const K5: u64 = 0x1_63cd_6124;
+// P itself and floor(x^64 / P) for Barrett reduction
+const P_X: u64 = 0x1_db71_0641;
+const U_PRIME: u64 = 0x1_f701_1641;
+
+/// inputs shorter than this are left to the table as folding doesn't pay off
+pub const MIN_LENGTH: usize = 64;
+
+// the generic version serves the PMULL path and tests
+#[cfg_attr(not(target_arch = "aarch64"), allow(unused))]
+const LOW_32: u128 = 0xffff_ffff;
+
+#[cfg_attr(not(target_arch = "aarch64"), allow(unused))]
+fn load(buf: &[u8]) -> u128 {
+    u128::from_le_bytes(buf[..16].try_into().unwrap())
+}
+
+/// updates `c`, the CRC-32 register before the final inversion, with the
+/// bytes of `buf` in 16-byte units; returns it along with the bytes left.
+/// `clmul` is the carry-less product of two 64-bit polynomials.
+///
+/// `buf` must be at least `MIN_LENGTH` bytes long.
+#[cfg_attr(not(target_arch = "aarch64"), allow(unused))]
+#[inline(always)]
+pub fn fold<C>(c: u32, buf: &[u8], clmul: C) -> (u32, &[u8])
+where
+    C: Fn(u64, u64) -> u128,
+{
+    debug_assert!(buf.len() >= MIN_LENGTH);
+
+    // x * x^n = (lo + hi * x^64) * x^n, with each product reduced to at most
+    // 96 bits by the constants
+    let fold_by =
+        |x: u128, k_lo: u64, k_hi: u64| clmul(x as u64, k_lo) ^ clmul((x >> 64) as u64, k_hi);
+
+    let mut chunks = buf.chunks_exact(16);
+    let mut next = || load(chunks.next().unwrap());
+    let mut x = [next() ^ u128::from(c), next(), next(), next()];
+
+    let mut rest = &buf[MIN_LENGTH..];
+    while rest.len() >= 64 {
+        for (i, x) in x.iter_mut().enumerate() {
+            *x = fold_by(*x, K1, K2) ^ load(&rest[i * 16..]);
+        }
+        rest = &rest[64..];
+    }
+
+    let mut x = x[1..]
+        .iter()
+        .fold(x[0], |acc, &next| fold_by(acc, K3, K4) ^ next);
+    while rest.len() >= 16 {
+        x = fold_by(x, K3, K4) ^ load(rest);
+        rest = &rest[16..];
+    }
+
+    // 128 bits to 64
+    let x = clmul(x as u64, K4) ^ (x >> 64);
+    let x = clmul((x & LOW_32) as u64, K5) ^ (x >> 32);
+
+    // Barrett reduction; the reflected remainder is in the upper half
+    let t1 = clmul((x & LOW_32) as u64, U_PRIME);
+    let t2 = clmul((t1 & LOW_32) as u64, P_X);
+    (((x ^ t2) >> 32) as u32, rest)
+}
+
+#[cfg(target_arch = "x86_64")]
+pub mod x86_64 {
+    use super::*;
+    use std::arch::x86_64::*;
+
+    // the lower half of `x` times that of `keys`, xored with the upper halves
+    // multiplied likewise. Safe functions with `target_feature` need Rust 1.86.
+    #[inline]
+    #[target_feature(enable = "pclmulqdq,sse2")]
+    unsafe fn fold_by(x: __m128i, keys: __m128i) -> __m128i {
+        _mm_xor_si128(
+            _mm_clmulepi64_si128(x, keys, 0x00),
+            _mm_clmulepi64_si128(x, keys, 0x11),
+        )
+    }
+
+    /// same as `fold` with PCLMULQDQ, keeping all 128 bits in a register.
+    ///
+    /// # Safety
+    ///
+    /// The CPU must support PCLMULQDQ. SSE2 is part of x86_64 and needs no
+    /// check.
+    #[target_feature(enable = "pclmulqdq,sse2")]
+    pub unsafe fn update(c: u32, buf: &[u8]) -> (u32, &[u8]) {
+        debug_assert!(buf.len() >= MIN_LENGTH);
+
+        // unaligned loads of 16 bytes within `buf`
+        let load = |buf: &[u8]| unsafe { _mm_loadu_si128(buf.as_ptr() as *const __m128i) };
+
+        let mut x0 = _mm_xor_si128(load(buf), _mm_cvtsi32_si128(c as i32));
+        let mut x1 = load(&buf[16..]);
+        let mut x2 = load(&buf[32..]);
+        let mut x3 = load(&buf[48..]);
+
+        let k1k2 = _mm_set_epi64x(K2 as i64, K1 as i64);
+        let mut rest = &buf[MIN_LENGTH..];
+        while rest.len() >= 64 {
+            x0 = _mm_xor_si128(fold_by(x0, k1k2), load(rest));
+            x1 = _mm_xor_si128(fold_by(x1, k1k2), load(&rest[16..]));
+            x2 = _mm_xor_si128(fold_by(x2, k1k2), load(&rest[32..]));
+            x3 = _mm_xor_si128(fold_by(x3, k1k2), load(&rest[48..]));
+            rest = &rest[64..];
+        }
+
+        let k3k4 = _mm_set_epi64x(K4 as i64, K3 as i64);
+        let mut x = _mm_xor_si128(fold_by(x0, k3k4), x1);
+        x = _mm_xor_si128(fold_by(x, k3k4), x2);
+        x = _mm_xor_si128(fold_by(x, k3k4), x3);
+        while rest.len() >= 16 {
+            x = _mm_xor_si128(fold_by(x, k3k4), load(rest));
+            rest = &rest[16..];
+        }
+
+        // 128 bits to 64
+        let low_32 = _mm_set_epi32(0, 0, 0, !0);
+        let x = _mm_xor_si128(_mm_clmulepi64_si128(x, k3k4, 0x10), _mm_srli_si128(x, 8));
+        let x = _mm_xor_si128(
+            _mm_clmulepi64_si128(_mm_and_si128(x, low_32), _mm_set_epi64x(0, K5 as i64), 0x00),
+            _mm_srli_si128(x, 4),
+        );
+
+        // Barrett reduction; the reflected remainder is in the upper half
+        let pu = _mm_set_epi64x(U_PRIME as i64, P_X as i64);
+        let t1 = _mm_clmulepi64_si128(_mm_and_si128(x, low_32), pu, 0x10);
+        let t2 = _mm_clmulepi64_si128(_mm_and_si128(t1, low_32), pu, 0x00);
+        let c = _mm_cvtsi128_si32(_mm_srli_si128(_mm_xor_si128(x, t2), 4)) as u32;
+
+        (c, rest)
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+pub mod aarch64 {
+    use super::*;
+    use std::arch::aarch64::vmull_p64;
+
+    /// same as `fold` with PMULL.
+    ///
+    /// # Safety
+    ///
+    /// The CPU must support PMULL.
+    #[target_feature(enable = "neon,aes")]
+    pub unsafe fn update(c: u32, buf: &[u8]) -> (u32, &[u8]) {
+        fold(c, buf, |a, b| unsafe { vmull_p64(a, b) })
+    }
+}
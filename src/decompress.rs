@@ -1,80 +1,195 @@
-mod huffman;
+pub mod huffman;
+mod inflater;
 mod raw;
+mod table;
 
+use crate::crc32::Crc32;
+use crate::dictionary::Dictionary;
+use crate::header::{parse_subfields, ExtraSubfield, GzipFlags, GzipHeader};
 use crate::reader::Reader;
 use crate::writer::Writer;
 use crate::DecompressOptions;
 use anyhow::{bail, Context, Result};
 use encoding_rs::mem::decode_latin1;
 use std::convert::TryInto;
-use std::io::{Read, Write};
+use std::io::{BufRead, Read, Write};
+use table::DecodeTable;
 
-// returns (bytes decompressed, whether this is the final block)
-pub fn decompress_block<R, W>(
-    reader: &mut Reader<R>,
-    writer: &mut Writer<W>,
-) -> Result<(usize, bool)>
-where
-    R: Read,
-    W: Write,
-{
-    let final_block = reader.next_bit()?;
-    let bytes = match [reader.next_bit()?, reader.next_bit()?] {
-        [false, false] => raw::decompress(reader, writer)?,
-        [true, false] => huffman::decompress_fixed(reader, writer)?,
-        [false, true] => huffman::decompress_dynamic(reader, writer)?,
-        _ => bail!("block type 11 is reserved"),
-    };
+pub use inflater::{InflateProgress, InflateStatus, Inflater};
 
-    Ok((bytes, final_block))
+enum BlockState {
+    // about to read the header of the next block
+    Start,
+    // bytes of a stored block yet to be copied
+    Stored(usize),
+    Fixed,
+    Dynamic(DecodeTable, DecodeTable),
+    Done,
 }
 
-struct GzipFlags(u8);
+/// DEFLATE decoder that can stop whenever enough output has been produced
+/// and resume later on, block by block and symbol by symbol.
+pub struct Inflate {
+    state: BlockState,
+    final_block: bool,
+}
 
-impl GzipFlags {
-    #[allow(unused)]
-    fn is_text(&self) -> bool {
-        (self.0 & 0x01) > 0
+impl Inflate {
+    pub fn new() -> Self {
+        Self {
+            state: BlockState::Start,
+            final_block: false,
+        }
     }
 
-    fn has_crc(&self) -> bool {
-        (self.0 & 0x02) > 0
+    /// whether the final block has been decoded entirely
+    pub fn is_done(&self) -> bool {
+        matches!(self.state, BlockState::Done)
     }
 
-    fn has_extra(&self) -> bool {
-        (self.0 & 0x04) > 0
+    // moves to the next block, or finishes after the final one
+    fn end_block(&mut self) {
+        self.state = if self.final_block {
+            BlockState::Done
+        } else {
+            BlockState::Start
+        };
     }
 
-    fn has_name(&self) -> bool {
-        (self.0 & 0x08) > 0
+    /// decodes until at least `wanted` bytes are produced or the final block
+    /// ends; returns the bytes produced. May produce up to 257 bytes more than
+    /// `wanted` as a match is never split.
+    pub fn decode<R, W>(
+        &mut self,
+        reader: &mut Reader<R>,
+        writer: &mut Writer<W>,
+        wanted: usize,
+    ) -> Result<usize>
+    where
+        R: BufRead,
+        W: Write,
+    {
+        let mut bytes = 0;
+        while bytes < wanted && !self.is_done() {
+            bytes += self.step(reader, writer, wanted - bytes)?;
+        }
+        Ok(bytes)
     }
 
-    fn has_comment(&self) -> bool {
-        (self.0 & 0x10) > 0
+    // reads the header of the next block, or decodes the current one until at
+    // least `wanted` bytes are produced or it ends; returns the bytes
+    // produced. A step that fails leaves the block state as it was, so it can
+    // be taken again from where it started, though `writer` may have part of
+    // its output unless it decodes a single symbol.
+    fn step<R, W>(
+        &mut self,
+        reader: &mut Reader<R>,
+        writer: &mut Writer<W>,
+        wanted: usize,
+    ) -> Result<usize>
+    where
+        R: BufRead,
+        W: Write,
+    {
+        let (lit_table, dist_table) = match &mut self.state {
+            BlockState::Start => {
+                self.final_block = reader.next_bit()?;
+                self.state = match [reader.next_bit()?, reader.next_bit()?] {
+                    [false, false] => BlockState::Stored(raw::read_header(reader)?),
+                    [true, false] => BlockState::Fixed,
+                    [false, true] => {
+                        let (lit_table, dist_table) = huffman::read_dynamic_tables(reader)?;
+                        BlockState::Dynamic(lit_table, dist_table)
+                    }
+                    _ => bail!("block type 11 is reserved"),
+                };
+                return Ok(0);
+            }
+            BlockState::Stored(remain) => {
+                let length = std::cmp::min(*remain, wanted);
+                writer.copy_from(reader, length)?;
+                *remain -= length;
+
+                if *remain == 0 {
+                    self.end_block();
+                }
+                return Ok(length);
+            }
+            BlockState::Fixed => {
+                let (lit_table, dist_table) = huffman::fixed_tables();
+                (lit_table, dist_table)
+            }
+            BlockState::Dynamic(lit_table, dist_table) => (&*lit_table, &*dist_table),
+            BlockState::Done => return Ok(0),
+        };
+
+        let (produced, end_of_block) =
+            huffman::read_compressed_data(reader, writer, lit_table, dist_table, wanted)?;
+        if end_of_block {
+            self.end_block();
+        }
+        Ok(produced)
     }
 }
 
-pub fn decompress<R, W>(reader: &mut R, writer: &mut W, opts: &DecompressOptions) -> Result<()>
+// reads header fields while keeping the CRC-32 of every byte consumed, for
+// FHCRC
+struct HeaderReader<'a, R> {
+    reader: &'a mut Reader<R>,
+    crc: Crc32,
+}
+
+impl<R> HeaderReader<'_, R>
 where
-    R: Read,
-    W: Write,
+    R: BufRead,
 {
-    let mut reader = Reader::new(reader);
-    // maximum distance is 32768
-    let mut writer = Writer::new(writer, 32768);
+    fn next_byte(&mut self) -> Result<u8> {
+        let byte = self.reader.next_byte()?;
+        self.crc.update(&[byte]);
+        Ok(byte)
+    }
 
-    // header verification
+    // reads up to `length` bytes; fewer are returned only at the end of input
+    fn read_bytes(&mut self, length: usize) -> Result<Vec<u8>> {
+        let mut buf = Vec::with_capacity(length);
+        self.reader.copy_to(&mut buf, length)?;
+        self.crc.update(&buf);
+        Ok(buf)
+    }
+
+    // reads a zero-terminated string, excluding the terminator
+    fn read_zero_terminated(&mut self) -> Result<Vec<u8>> {
+        let mut buf = vec![];
+        loop {
+            let byte = self.next_byte()?;
+            if byte == 0 {
+                break;
+            } else {
+                buf.push(byte);
+            }
+        }
+        Ok(buf)
+    }
+}
+
+pub(crate) fn read_header<R>(reader: &mut Reader<R>) -> Result<GzipHeader>
+where
+    R: BufRead,
+{
+    let mut reader = HeaderReader {
+        reader,
+        crc: Crc32::new(),
+    };
 
     // magic number
-    let mut ids = [0; 2];
-    reader
-        .copy_to(&mut &mut ids[..], 2)
+    let ids = reader
+        .read_bytes(2)
         .context("failed to read magic numbers")?;
-    if ids[0] != 0x1f || ids[1] != 0x8b {
+    if ids.len() != 2 || ids[0] != 0x1f || ids[1] != 0x8b {
         bail!(
             "wrong magic number; ID1 = {:#x} (expected 0x1f), ID2 = {:#x} (expected 0x8b)",
-            ids[0],
-            ids[1],
+            ids.first().copied().unwrap_or(0),
+            ids.get(1).copied().unwrap_or(0),
         );
     }
 
@@ -96,89 +211,118 @@ where
         reader.next_byte()?,
     ];
     let mtime = u32::from_le_bytes(mtime_bytes);
-    let extra_flag = reader.next_byte()?;
+    let extra_flags = reader.next_byte()?;
     let os = reader.next_byte()?;
 
-    if flags.has_extra() {
+    let (extra, extra_subfields) = if flags.has_extra() {
         let length_bytes = [reader.next_byte()?, reader.next_byte()?];
         let length = u16::from_le_bytes(length_bytes).into();
-        // TODO: handle extra fields properly
-        let consumed = reader.skip(length)?;
+        let extra = reader.read_bytes(length)?;
 
-        if length != consumed {
+        if length != extra.len() {
             bail!(
                 "extra field: failed to read {} bytes; only {} bytes were read",
                 length,
-                consumed,
+                extra.len(),
             );
         }
-    }
 
-    let original_name = if flags.has_name() {
-        let mut buf = vec![];
-        loop {
-            let byte = reader.next_byte()?;
-            if byte == 0 {
-                break;
-            } else {
-                buf.push(byte);
-            }
-        }
+        let subfields = parse_subfields(&extra)?;
+        (Some(extra), subfields)
+    } else {
+        (None, vec![])
+    };
 
-        let name = decode_latin1(&buf[..]);
-        Some(name.into_owned())
+    let raw_name = if flags.has_name() {
+        Some(reader.read_zero_terminated()?)
     } else {
         None
     };
 
-    let comment = if flags.has_comment() {
-        let mut buf = vec![];
-        loop {
-            let byte = reader.next_byte()?;
-            if byte == 0 {
-                break;
-            } else {
-                buf.push(byte);
-            }
-        }
-
-        let comment = decode_latin1(&buf[..]);
-        Some(comment.into_owned())
+    let raw_comment = if flags.has_comment() {
+        Some(reader.read_zero_terminated()?)
     } else {
         None
     };
 
-    // TODO: check crc16
-    let header_crc16 = if flags.has_crc() {
+    let header_crc = if flags.has_crc() {
+        // the lower 16 bits of CRC-32 of all the bytes up to here
+        let actual = (reader.crc.sum() & 0xffff) as u16;
+
         let bytes = [reader.next_byte()?, reader.next_byte()?];
         let crc = u16::from_le_bytes(bytes);
+        if crc != actual {
+            bail!(
+                "header CRC mismatch; expected = {:#06x}, actual = {:#06x}",
+                crc,
+                actual,
+            );
+        }
         Some(crc)
     } else {
         None
     };
 
-    if opts.show_header {
-        let os = match os {
-            0 => "FAT filesystem",
-            1 => "Amiga",
-            2 => "VMS",
-            3 => "Unix",
-            4 => "VM/CMS",
-            5 => "Atari TOS",
-            6 => "HPFS filesystem",
-            7 => "Macintosh",
-            8 => "Z-System",
-            9 => "CP/M",
-            10 => "TOPS-20",
-            11 => "NTFS filesystem",
-            12 => "QDOS",
-            13 => "Acorn RISCOS",
-            255 => "unknown",
-            _ => "unknown (undefined value)",
-        };
+    Ok(GzipHeader {
+        flags,
+        mtime,
+        extra_flags,
+        os: os.into(),
+        extra,
+        extra_subfields,
+        name: raw_name
+            .as_deref()
+            .map(|name| decode_latin1(name).into_owned()),
+        raw_name,
+        comment: raw_comment
+            .as_deref()
+            .map(|comment| decode_latin1(comment).into_owned()),
+        raw_comment,
+        header_crc,
+    })
+}
+
+// human readable form of a subfield, decoding the known ones
+fn describe_subfield(subfield: &ExtraSubfield) -> String {
+    let data = &subfield.data;
+    let u16_at = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]);
 
-        eprintln!(
-            r"magic number      : {:#x} {:#x}
+    match subfield.id {
+        // VER, CHLEN, CHCNT and CHCNT compressed chunk sizes
+        ExtraSubfield::DICTZIP
+            if data.len() >= 6 && data.len() == 6 + 2 * usize::from(u16_at(4)) =>
+        {
+            format!(
+                "'RA' (dictzip) version {}, chunk length {}, {} chunks",
+                u16_at(0),
+                u16_at(2),
+                u16_at(4),
+            )
+        }
+        // BSIZE, the total block size minus 1
+        ExtraSubfield::BGZF if data.len() == 2 => {
+            format!("'BC' (BGZF) block size {}", u32::from(u16_at(0)) + 1)
+        }
+        id => format!("'{}' {} bytes", String::from_utf8_lossy(&id), data.len()),
+    }
+}
+
+fn print_header(header: &GzipHeader) {
+    let extra_field = if header.extra.is_none() {
+        "(not set)".to_string()
+    } else if header.extra_subfields.is_empty() {
+        "(empty)".to_string()
+    } else {
+        header
+            .extra_subfields
+            .iter()
+            .map(describe_subfield)
+            .collect::<Vec<_>>()
+            .join("\n                    ")
+    };
+
+    eprintln!(
+        r"magic number      : {:#x} {:#x}
 compression method: {:#04x}
 flags             : {:#04x}
          FTEXT    : {}
@@ -189,50 +333,48 @@ flags             : {:#04x}
 modification time : {}
 extra flags       : {:#04x}
 os                : {}
+extra field       : {}
 original file name: {}
 comment           : {}
 header CRC        : {}",
-            ids[0],
-            ids[1],
-            cm,
-            flags.0,
-            flags.is_text(),
-            flags.has_crc(),
-            flags.has_extra(),
-            flags.has_name(),
-            flags.has_comment(),
-            mtime,
-            extra_flag,
-            os,
-            original_name.unwrap_or_else(|| "(not set)".into()),
-            comment.unwrap_or_else(|| "(not set)".into()),
-            header_crc16
-                .map(|n| format!("{:#06x}", n))
-                .unwrap_or_else(|| "(not set)".into()),
-        );
-    }
-
-    // actual decompression
-    let mut total_bytes = 0;
-    loop {
-        let (bytes, final_block) = decompress_block(&mut reader, &mut writer)?;
-        total_bytes += bytes;
-        if final_block {
-            break;
-        }
-    }
+        0x1f,
+        0x8b,
+        0x08,
+        header.flags.0,
+        header.flags.is_text(),
+        header.flags.has_crc(),
+        header.flags.has_extra(),
+        header.flags.has_name(),
+        header.flags.has_comment(),
+        header.mtime,
+        header.extra_flags,
+        header.os.name(),
+        extra_field,
+        header.name.as_deref().unwrap_or("(not set)"),
+        header.comment.as_deref().unwrap_or("(not set)"),
+        header
+            .header_crc
+            .map(|n| format!("{:#06x}", n))
+            .unwrap_or_else(|| "(not set)".into()),
+    );
+}
 
+// reads the trailer and verifies it against the CRC-32 and the length of the
+// decompressed data
+fn read_trailer<R>(reader: &mut Reader<R>, data_crc32: u32, total_bytes: usize) -> Result<()>
+where
+    R: BufRead,
+{
     // TODO: check unread bits if any
     reader.ensure_byte_boundary()?;
 
-    // TODO: check crc32
     let data_crc32_bytes = [
         reader.next_byte()?,
         reader.next_byte()?,
         reader.next_byte()?,
         reader.next_byte()?,
     ];
-    let _data_crc32 = u32::from_le_bytes(data_crc32_bytes);
+    let expected_crc32 = u32::from_le_bytes(data_crc32_bytes);
 
     let data_length_bytes = [
         reader.next_byte()?,
@@ -250,7 +392,637 @@ header CRC        : {}",
         );
     }
 
-    writer.flush()?;
+    if data_crc32 != expected_crc32 {
+        bail!(
+            "CRC-32 mismatch; expected = {:#010x}, actual = {:#010x}",
+            expected_crc32,
+            data_crc32,
+        );
+    }
 
     Ok(())
 }
+
+// after a member, tells whether another one follows. Like gzip, zeros padding
+// the input to a block boundary end the stream, and so does anything else not
+// starting with the magic number, with a warning.
+fn has_next_member<R>(reader: &mut Reader<R>) -> Result<bool>
+where
+    R: BufRead,
+{
+    if !reader.has_more_bytes()? {
+        return Ok(false);
+    }
+    if reader.fill_bits(16)? >= 16 && reader.peek_bits(16)? == 0x8b1f {
+        return Ok(true);
+    }
+
+    while reader.has_more_bytes()? {
+        if reader.next_byte()? != 0 {
+            eprintln!("warning: trailing garbage ignored");
+            break;
+        }
+    }
+    Ok(false)
+}
+
+/// decompresses every member in `reader`; returns the header of each.
+pub fn decompress<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    opts: &DecompressOptions,
+) -> Result<Vec<GzipHeader>>
+where
+    R: BufRead,
+    W: Write,
+{
+    let mut reader = Reader::new(reader);
+    // maximum distance is 32768
+    let mut writer = Writer::new(writer, 32768);
+
+    let mut headers = vec![];
+
+    // a gzip file may consist of multiple members, whose outputs are
+    // concatenated
+    loop {
+        let header = read_header(&mut reader)?;
+
+        if opts.show_header {
+            if !headers.is_empty() {
+                eprintln!();
+            }
+            print_header(&header);
+        }
+        headers.push(header);
+
+        // actual decompression
+        let total_bytes = Inflate::new().decode(&mut reader, &mut writer, usize::MAX)?;
+
+        read_trailer(&mut reader, writer.crc32(), total_bytes)?;
+
+        if !has_next_member(&mut reader)? {
+            break;
+        }
+        writer.reset()?;
+    }
+
+    writer.flush()?;
+
+    Ok(headers)
+}
+
+// upper bound of bytes decoded ahead of what the caller of `read()` asked for
+const DECODE_CHUNK_SIZE: usize = 32768;
+
+/// Decompressor of a gzip stream that implements `Read`.
+///
+/// Input is consumed only as output is requested, and memory use stays bounded
+/// no matter how large the stream is. Wrap `reader` with `BufReader` unless
+/// it's buffered already.
+pub struct GzDecoder<R> {
+    reader: Reader<R>,
+    // decoded bytes are gathered here until they're handed to the caller
+    writer: Writer<Vec<u8>>,
+    pos: usize,
+    inflate: Inflate,
+    // header of the current member; None until it's read
+    header: Option<GzipHeader>,
+    total_bytes: usize,
+    finished: bool,
+}
+
+impl<R> GzDecoder<R>
+where
+    R: BufRead,
+{
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader: Reader::new(reader),
+            // maximum distance is 32768
+            writer: Writer::new(Vec::new(), 32768),
+            pos: 0,
+            inflate: Inflate::new(),
+            header: None,
+            total_bytes: 0,
+            finished: false,
+        }
+    }
+
+    /// returns the header of the member being decoded, reading it first if no
+    /// data has been read from that member yet.
+    pub fn header(&mut self) -> std::io::Result<&GzipHeader> {
+        if self.header.is_none() {
+            let header = read_header(&mut self.reader).map_err(crate::into_io_error)?;
+            self.header = Some(header);
+        }
+        Ok(self.header.as_ref().unwrap())
+    }
+
+    // decodes more data into `self.writer`; returns false at the end of stream
+    fn fill(&mut self, wanted: usize) -> Result<bool> {
+        if self.finished {
+            return Ok(false);
+        }
+
+        if self.header.is_none() {
+            self.header = Some(read_header(&mut self.reader)?);
+        }
+
+        if self.inflate.is_done() {
+            read_trailer(&mut self.reader, self.writer.crc32(), self.total_bytes)?;
+
+            // the next member, if any, follows right after the trailer
+            if has_next_member(&mut self.reader)? {
+                self.header = None;
+                self.inflate = Inflate::new();
+                self.total_bytes = 0;
+                self.writer.reset()?;
+                return Ok(true);
+            }
+
+            self.finished = true;
+            return Ok(false);
+        }
+
+        let wanted = std::cmp::min(wanted, DECODE_CHUNK_SIZE);
+        let bytes = self
+            .inflate
+            .decode(&mut self.reader, &mut self.writer, wanted)?;
+        self.writer.flush()?;
+        self.total_bytes = self.total_bytes.wrapping_add(bytes);
+
+        Ok(true)
+    }
+}
+
+impl<R> Read for GzDecoder<R>
+where
+    R: BufRead,
+{
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            let amount = take_pending(&mut self.writer, &mut self.pos, buf);
+            if amount > 0 {
+                return Ok(amount);
+            }
+
+            if !self.fill(buf.len()).map_err(crate::into_io_error)? {
+                return Ok(0);
+            }
+        }
+    }
+}
+
+// copies decoded bytes from `pending[*pos..]` to `buf`; returns the number of
+// bytes copied. `pending` is emptied once everything in it has been taken.
+fn take_pending(pending: &mut Writer<Vec<u8>>, pos: &mut usize, buf: &mut [u8]) -> usize {
+    let rest = &pending.get_ref()[*pos..];
+    let amount = std::cmp::min(rest.len(), buf.len());
+    buf[..amount].copy_from_slice(&rest[..amount]);
+    *pos += amount;
+
+    if *pos == pending.get_ref().len() {
+        pending.get_mut().clear();
+        *pos = 0;
+    }
+    amount
+}
+
+/// Result of decoding a raw DEFLATE stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InflateStats {
+    /// bits consumed up to the end of the final block
+    pub bits_consumed: u64,
+    pub bytes_written: u64,
+}
+
+impl InflateStats {
+    /// bytes consumed, including the one holding the last bit of the final
+    /// block; the data following the stream starts here.
+    pub fn bytes_consumed(&self) -> u64 {
+        self.bits_consumed.div_ceil(8)
+    }
+}
+
+/// decompresses a raw DEFLATE stream, without any header or trailer.
+///
+/// `reader` is never consumed past the byte holding the last bit of the final
+/// block, so whatever follows can be read from it right
+/// away. `dictionary` must be the one the stream was compressed with, if any.
+pub fn inflate_raw<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    dictionary: Option<&Dictionary>,
+) -> Result<InflateStats>
+where
+    R: BufRead,
+    W: Write,
+{
+    let mut reader = Reader::new(reader);
+    // maximum distance is 32768
+    let mut writer = Writer::new(writer, 32768);
+    if let Some(dictionary) = dictionary {
+        writer.set_dictionary(dictionary.as_bytes());
+    }
+
+    let bytes_written = Inflate::new().decode(&mut reader, &mut writer, usize::MAX)?;
+    writer.flush()?;
+
+    let bits_consumed = reader.bit_position();
+    // gives back the bytes loaded ahead
+    reader.into_inner();
+
+    Ok(InflateStats {
+        bits_consumed,
+        bytes_written: bytes_written as u64,
+    })
+}
+
+/// Decompressor of a raw DEFLATE stream that implements `Read`.
+///
+/// Like `inflate_raw`, nothing past the final block is taken from `reader`,
+/// which `into_inner` gives back.
+pub struct RawDecoder<R> {
+    reader: Reader<R>,
+    writer: Writer<Vec<u8>>,
+    pos: usize,
+    inflate: Inflate,
+}
+
+impl<R> RawDecoder<R>
+where
+    R: BufRead,
+{
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader: Reader::new(reader),
+            // maximum distance is 32768
+            writer: Writer::new(Vec::new(), 32768),
+            pos: 0,
+            inflate: Inflate::new(),
+        }
+    }
+
+    /// for a stream compressed with `dictionary`
+    pub fn with_dictionary(reader: R, dictionary: &Dictionary) -> Self {
+        let mut decoder = Self::new(reader);
+        decoder.writer.set_dictionary(dictionary.as_bytes());
+        decoder
+    }
+
+    /// bits consumed so far; see `InflateStats::bits_consumed`.
+    pub fn bits_consumed(&self) -> u64 {
+        self.reader.bit_position()
+    }
+
+    /// whether the final block has been decoded entirely
+    pub fn is_done(&self) -> bool {
+        self.inflate.is_done()
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader.into_inner()
+    }
+}
+
+impl<R> Read for RawDecoder<R>
+where
+    R: BufRead,
+{
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            let amount = take_pending(&mut self.writer, &mut self.pos, buf);
+            if amount > 0 || self.inflate.is_done() {
+                return Ok(amount);
+            }
+
+            let wanted = std::cmp::min(buf.len(), DECODE_CHUNK_SIZE);
+            self.inflate
+                .decode(&mut self.reader, &mut self.writer, wanted)
+                .map_err(crate::into_io_error)?;
+            self.writer.flush()?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bit_writer::BitWriter;
+    use crate::test_util::sample;
+    use crate::{CompressOptions, GzEncoder};
+    use huffman::ALPHABET_ORDER;
+
+    fn compress(data: &[u8], opts: CompressOptions) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(vec![], opts);
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn decoder_reads_in_small_pieces() {
+        let data = sample(20000);
+        for level in [0, 6] {
+            let opts = CompressOptions {
+                level: crate::CompressionLevel::new(level).unwrap(),
+                ..Default::default()
+            };
+            let compressed = compress(&data, opts);
+
+            let mut decoder = GzDecoder::new(&compressed[..]);
+            let mut decompressed = vec![];
+            let mut buf = [0; 100];
+            loop {
+                let n = decoder.read(&mut buf).unwrap();
+                if n == 0 {
+                    break;
+                }
+                assert!(decoder.writer.get_ref().len() <= DECODE_CHUNK_SIZE + 258);
+                decompressed.extend_from_slice(&buf[..n]);
+            }
+
+            assert_eq!(decompressed, data);
+        }
+    }
+
+    #[test]
+    fn members_are_concatenated() {
+        let data = sample(20000);
+        let (first, second) = data.split_at(30000);
+        let mut compressed = compress(first, Default::default());
+        compressed.extend(compress(b"", Default::default()));
+        compressed.extend(compress(second, Default::default()));
+
+        let opts = DecompressOptions {
+            show_header: false,
+            no_emit: false,
+        };
+        let mut decompressed = vec![];
+        decompress(&mut &compressed[..], &mut decompressed, &opts).unwrap();
+        assert_eq!(decompressed, data);
+
+        let mut decompressed = vec![];
+        GzDecoder::new(&compressed[..])
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, data);
+    }
+
+    #[test]
+    fn headers_are_returned() {
+        let mut compressed = vec![];
+        crate::compress::compress(
+            &mut &b"hello"[..],
+            &mut compressed,
+            Some("h\u{e9}llo.txt"),
+            1234,
+            &Default::default(),
+        )
+        .unwrap();
+        compressed.extend(compress(b"", Default::default()));
+
+        let opts = DecompressOptions {
+            show_header: false,
+            no_emit: true,
+        };
+        let headers = decompress(&mut &compressed[..], &mut std::io::sink(), &opts).unwrap();
+        assert_eq!(headers.len(), 2);
+        assert_eq!(headers[0].name.as_deref(), Some("h\u{e9}llo.txt"));
+        assert_eq!(headers[0].raw_name.as_deref(), Some(&b"h\xe9llo.txt"[..]));
+        assert_eq!(headers[0].mtime, 1234);
+        assert_eq!(headers[1].name, None);
+
+        let mut decoder = GzDecoder::new(&compressed[..]);
+        assert_eq!(decoder.header().unwrap(), &headers[0]);
+        let mut decompressed = vec![];
+        decoder.read_to_end(&mut decompressed).unwrap();
+        assert_eq!(decompressed, b"hello");
+    }
+
+    #[test]
+    fn each_member_is_verified() {
+        let mut compressed = compress(b"first", Default::default());
+        let mut second = compress(b"second", Default::default());
+        let len = second.len();
+        // CRC-32 of the second member
+        second[len - 8] ^= 0x01;
+        compressed.extend(second);
+
+        let opts = DecompressOptions {
+            show_header: false,
+            no_emit: true,
+        };
+        let err = decompress(&mut &compressed[..], &mut std::io::sink(), &opts).unwrap_err();
+        assert!(err.to_string().contains("CRC-32 mismatch"), "{}", err);
+    }
+
+    #[test]
+    fn matches_cannot_reach_previous_member() {
+        let first = b"hello, hello";
+        let mut compressed = compress(first, Default::default());
+
+        // a member referring to the first one as if it were a dictionary
+        let dictionary = crate::Dictionary::new(&first[..]);
+        let mut encoder =
+            crate::RawEncoder::with_dictionary(vec![], &Default::default(), &dictionary);
+        encoder.write_all(first).unwrap();
+        let deflated = encoder.finish().unwrap();
+        compressed.extend_from_slice(&[0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff]);
+        compressed.extend_from_slice(&deflated);
+        let mut crc = Crc32::new();
+        crc.update(first);
+        compressed.extend_from_slice(&crc.sum().to_le_bytes());
+        compressed.extend_from_slice(&(first.len() as u32).to_le_bytes());
+
+        let opts = DecompressOptions {
+            show_header: false,
+            no_emit: true,
+        };
+        let err = decompress(&mut &compressed[..], &mut std::io::sink(), &opts).unwrap_err();
+        assert!(err.to_string().contains("invalid distance"), "{}", err);
+
+        let err =
+            std::io::copy(&mut GzDecoder::new(&compressed[..]), &mut std::io::sink()).unwrap_err();
+        assert!(err.to_string().contains("invalid distance"), "{}", err);
+    }
+
+    #[test]
+    fn trailing_zeros_and_garbage_are_ignored() {
+        let opts = DecompressOptions {
+            show_header: false,
+            no_emit: false,
+        };
+        for trailer in [&[0; 512][..], b"\0\0garbage", b"\x1f"] {
+            let mut compressed = compress(b"hello", Default::default());
+            compressed.extend_from_slice(trailer);
+
+            let mut decompressed = vec![];
+            decompress(&mut &compressed[..], &mut decompressed, &opts).unwrap();
+            assert_eq!(decompressed, b"hello");
+
+            let mut decompressed = vec![];
+            GzDecoder::new(&compressed[..])
+                .read_to_end(&mut decompressed)
+                .unwrap();
+            assert_eq!(decompressed, b"hello");
+        }
+    }
+
+    #[test]
+    fn corrupted_data_fails_crc_check() {
+        let data = sample(20000);
+        let mut compressed = compress(
+            &data,
+            CompressOptions {
+                level: crate::CompressionLevel::NONE,
+                ..Default::default()
+            },
+        );
+        // flip a bit of a stored byte; the length stays intact
+        compressed[1000] ^= 0x10;
+
+        let opts = DecompressOptions {
+            show_header: false,
+            no_emit: true,
+        };
+        let err = decompress(&mut &compressed[..], &mut std::io::sink(), &opts).unwrap_err();
+        assert!(err.to_string().contains("CRC-32 mismatch"), "{}", err);
+    }
+
+    #[test]
+    fn raw_stream_reports_consumed_input() {
+        let data = sample(20000);
+        let mut compressed = vec![];
+        crate::deflate_raw(&mut &data[..], &mut compressed, None, &Default::default()).unwrap();
+        let length = compressed.len();
+        compressed.extend_from_slice(b"trailer");
+
+        let mut input = &compressed[..];
+        let mut decompressed = vec![];
+        let stats = inflate_raw(&mut input, &mut decompressed, None).unwrap();
+        assert_eq!(decompressed, data);
+        assert_eq!(stats.bytes_written, data.len() as u64);
+        assert_eq!(stats.bytes_consumed(), length as u64);
+        assert!(stats.bits_consumed > (length as u64 - 1) * 8);
+        assert_eq!(input, b"trailer");
+
+        let mut decoder = RawDecoder::new(&compressed[..]);
+        let mut decompressed = vec![];
+        let mut buf = [0; 1000];
+        loop {
+            let n = decoder.read(&mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            decompressed.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(decompressed, data);
+        assert!(decoder.is_done());
+        assert_eq!(decoder.bits_consumed(), stats.bits_consumed);
+        assert_eq!(decoder.into_inner(), b"trailer");
+    }
+
+    #[test]
+    fn raw_stream_with_dictionary() {
+        let data = sample(20000);
+        let dictionary = Dictionary::new(&data[..5000]);
+        let mut compressed = vec![];
+        crate::deflate_raw(
+            &mut &data[5000..6000],
+            &mut compressed,
+            Some(&dictionary),
+            &Default::default(),
+        )
+        .unwrap();
+
+        let mut decompressed = vec![];
+        RawDecoder::with_dictionary(&compressed[..], &dictionary)
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, &data[5000..6000]);
+
+        // matches refer to the missing dictionary
+        let err = inflate_raw(&mut &compressed[..], &mut vec![], None).unwrap_err();
+        assert!(err.to_string().contains("invalid distance"), "{}", err);
+    }
+
+    #[test]
+    fn bits_consumed_is_exact() {
+        // fixed block: literal 'a' (8 bits), end of block (7 bits) and 3 header
+        // bits make 18 bits
+        let compressed = [0x4b, 0x04, 0x00, 0xff];
+        let mut decompressed = vec![];
+        let stats = inflate_raw(&mut &compressed[..], &mut decompressed, None).unwrap();
+        assert_eq!(decompressed, b"a");
+        assert_eq!(stats.bits_consumed, 18);
+        assert_eq!(stats.bytes_consumed(), 3);
+    }
+
+    // errors of both `inflate_raw` and `Inflater` on `compressed`
+    fn inflate_errors(compressed: &[u8]) -> [String; 2] {
+        let err = inflate_raw(&mut &compressed[..], &mut vec![], None).unwrap_err();
+        let push_err = Inflater::new()
+            .inflate(compressed, &mut [0; 1024])
+            .unwrap_err();
+        [err.to_string(), push_err.to_string()]
+    }
+
+    #[test]
+    fn dynamic_headers_are_checked_alike() {
+        // HLIT = 30, i.e. 287 literal/length codes
+        let mut writer = BitWriter::new(vec![]);
+        writer.write_bits(0b101, 3).unwrap();
+        writer.write_bits(30, 5).unwrap();
+        writer.write_bits(0, 9).unwrap();
+        for err in inflate_errors(&writer.finish().unwrap()) {
+            assert!(err.contains("too many"), "{}", err);
+        }
+
+        // every literal has a code, but the end of block doesn't
+        let mut writer = BitWriter::new(vec![]);
+        writer.write_bits(0b101, 3).unwrap();
+        // 257 literal/length codes, 1 distance code, 18 code length codes
+        writer.write_bits(0, 5).unwrap();
+        writer.write_bits(0, 5).unwrap();
+        writer.write_bits(14, 4).unwrap();
+        // code lengths 0 and 1 take 2 bits and 8 takes 1 bit
+        for symbol in &ALPHABET_ORDER[..18] {
+            let length = match symbol {
+                8 => 1,
+                0 | 1 => 2,
+                _ => 0,
+            };
+            writer.write_bits(length, 3).unwrap();
+        }
+        for _ in 0..256 {
+            writer.write_code(0b0, 1).unwrap();
+        }
+        // no end of block, length symbol 257 unused, distance code 1 bit long
+        writer.write_code(0b10, 2).unwrap();
+        writer.write_code(0b10, 2).unwrap();
+        writer.write_code(0b11, 2).unwrap();
+        for err in inflate_errors(&writer.finish().unwrap()) {
+            assert!(err.contains("end of block"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn decoder_reports_corruption_as_io_error() {
+        let mut compressed = compress(&sample(20000), Default::default());
+        let len = compressed.len();
+        // ISIZE
+        compressed[len - 1] ^= 0xff;
+
+        let mut decoder = GzDecoder::new(&compressed[..]);
+        let err = std::io::copy(&mut decoder, &mut std::io::sink()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::Other);
+    }
+}
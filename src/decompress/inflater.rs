@@ -0,0 +1,292 @@
+// Push-based DEFLATE decoder for event loops, on top of `Inflate`: input and
+// output are handed over as slices on every call and decoding stops wherever
+// either runs out, to be resumed by the next call.
+//
+// `Inflate` reads from a `Reader` and fails when input runs out in the middle
+// of a step, i.e. a block header, a literal or a match. Each step is given no
+// more to do than the input at hand surely covers, or else a single symbol.
+// If even that runs out of input, the bytes from the start of the step are
+// kept, and the step is taken again once more input arrives.
+
+use super::{take_pending, BlockState, Inflate};
+use crate::dictionary::Dictionary;
+use crate::reader::Reader;
+use crate::writer::Writer;
+use anyhow::Result;
+use std::io::{ErrorKind, Read};
+
+// maximum distance is 32768
+const WINDOW_SIZE: usize = 32768;
+
+// most bits a literal or a match takes: a length code of 15 bits with 5 extra
+// bits, followed by a distance code of 15 bits with 13 extra bits
+const MAX_SYMBOL_BITS: u64 = 48;
+
+/// Why `Inflater::inflate` returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InflateStatus {
+    /// all of the input has been taken; call again with more
+    NeedsInput,
+    /// the output is full; call again with more room
+    OutputFull,
+    /// the final block has ended
+    Done,
+}
+
+/// Result of a call to `Inflater::inflate`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InflateProgress {
+    pub status: InflateStatus,
+    /// bytes taken from the input; the rest must be passed in again
+    pub consumed: usize,
+    /// bytes written to the output
+    pub written: usize,
+}
+
+// whether `err` came from input running out
+fn is_end_of_input(err: &anyhow::Error) -> bool {
+    err.chain().any(|e| {
+        e.downcast_ref::<std::io::Error>()
+            .is_some_and(|e| e.kind() == ErrorKind::UnexpectedEof)
+    })
+}
+
+/// Resumable DEFLATE decoder that takes input and gives output as slices.
+///
+/// Decoding can be suspended anywhere, in the middle of a block header, code
+/// tables or a match alike. Once `Done`, whatever follows the stream starts
+/// right after the consumed input, the byte holding the last bit of the final
+/// block being consumed. The decoder must not be used after an error.
+pub struct Inflater {
+    inflate: Inflate,
+    // decoded bytes, which are the history for matches as well; those from
+    // `pos` on are yet to be handed out
+    writer: Writer<Vec<u8>>,
+    pos: usize,
+    // input taken already but not decoded yet, starting at the byte holding
+    // the next bit, and the bits of that byte decoded already
+    carry: Vec<u8>,
+    skip: u32,
+}
+
+impl Default for Inflater {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Inflater {
+    pub fn new() -> Self {
+        Self {
+            inflate: Inflate::new(),
+            writer: Writer::new(Vec::new(), WINDOW_SIZE),
+            pos: 0,
+            carry: Vec::new(),
+            skip: 0,
+        }
+    }
+
+    /// for a stream compressed with `dictionary`
+    pub fn with_dictionary(dictionary: &Dictionary) -> Self {
+        let mut inflater = Self::new();
+        inflater.writer.set_dictionary(dictionary.as_bytes());
+        inflater
+    }
+
+    /// whether the final block has been decoded and handed out entirely
+    pub fn is_done(&self) -> bool {
+        self.inflate.is_done() && self.writer.get_ref().is_empty()
+    }
+
+    /// decodes `input` into `output` until either runs out or the stream ends.
+    pub fn inflate(&mut self, input: &[u8], output: &mut [u8]) -> Result<InflateProgress> {
+        let carried = self.carry.len();
+        let total = carried + input.len();
+        let mut reader = Reader::new(self.carry.as_slice().chain(input));
+        reader.read_bits(self.skip)?;
+
+        let mut written = 0;
+        // where to go on from next time, and the bytes taken up to there
+        let (status, resume, taken) = loop {
+            written += take_pending(&mut self.writer, &mut self.pos, &mut output[written..]);
+            let position = reader.bit_position();
+            // a partially decoded byte is taken as well
+            let taken = std::cmp::max(position.div_ceil(8) as usize, carried);
+            if !self.writer.get_ref().is_empty() {
+                break (InflateStatus::OutputFull, position, taken);
+            }
+            if self.inflate.is_done() {
+                break (InflateStatus::Done, position, taken);
+            }
+            if written == output.len() {
+                break (InflateStatus::OutputFull, position, taken);
+            }
+
+            let available = total as u64 * 8 - position;
+            let room = output.len() - written;
+            let wanted = match self.inflate.state {
+                // stored blocks start at a byte boundary
+                BlockState::Stored(remain) if remain > 0 && available == 0 => {
+                    break (InflateStatus::NeedsInput, position, taken);
+                }
+                BlockState::Stored(_) => std::cmp::min(room, (available / 8) as usize),
+                _ => std::cmp::min(room, (available / MAX_SYMBOL_BITS) as usize).max(1),
+            };
+
+            match self.inflate.step(&mut reader, &mut self.writer, wanted) {
+                Ok(_) => self.writer.flush()?,
+                Err(e) if is_end_of_input(&e) => {
+                    // the step needs every byte from its start on
+                    break (InflateStatus::NeedsInput, position, total);
+                }
+                Err(e) => return Err(e),
+            }
+        };
+
+        if status == InflateStatus::Done {
+            self.carry.clear();
+            self.skip = 0;
+        } else {
+            let start = (resume / 8) as usize;
+            self.carry = (self.carry.iter().chain(input))
+                .skip(start)
+                .take(taken - start)
+                .copied()
+                .collect();
+            self.skip = (resume % 8) as u32;
+        }
+
+        Ok(InflateProgress {
+            status,
+            consumed: taken - carried,
+            written,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::sample;
+    use crate::{deflate_raw, inflate_raw, CompressOptions, CompressionLevel, Strategy};
+
+    fn deflate(data: &[u8], dictionary: Option<&Dictionary>, opts: &CompressOptions) -> Vec<u8> {
+        let mut compressed = vec![];
+        deflate_raw(&mut &data[..], &mut compressed, dictionary, opts).unwrap();
+        compressed
+    }
+
+    // output of `inflate_raw` on `compressed` and the input it consumed
+    fn inflate_whole(compressed: &[u8], dictionary: Option<&Dictionary>) -> (Vec<u8>, usize) {
+        let mut decompressed = vec![];
+        let stats = inflate_raw(&mut &compressed[..], &mut decompressed, dictionary).unwrap();
+        (decompressed, stats.bytes_consumed() as usize)
+    }
+
+    // feeds `compressed` `input_size` bytes at a time into an output buffer of
+    // `output_size` bytes; returns the output and the input consumed
+    fn inflate_in_pieces(
+        inflater: &mut Inflater,
+        compressed: &[u8],
+        input_size: usize,
+        output_size: usize,
+    ) -> (Vec<u8>, usize) {
+        let mut decompressed = vec![];
+        let mut output = vec![0; output_size];
+        let mut pos = 0;
+        loop {
+            let end = std::cmp::min(pos + input_size, compressed.len());
+            let progress = inflater
+                .inflate(&compressed[pos..end], &mut output)
+                .unwrap();
+            pos += progress.consumed;
+            decompressed.extend_from_slice(&output[..progress.written]);
+
+            match progress.status {
+                InflateStatus::Done => return (decompressed, pos),
+                InflateStatus::NeedsInput => assert_eq!(pos, end),
+                InflateStatus::OutputFull => assert_eq!(progress.written, output_size),
+            }
+        }
+    }
+
+    #[test]
+    fn resumes_anywhere() {
+        let data = sample(20000);
+        let options = [
+            (CompressionLevel::NONE, Strategy::Default),
+            (CompressionLevel::default(), Strategy::Fixed),
+            (CompressionLevel::default(), Strategy::Default),
+        ];
+        for (level, strategy) in options {
+            let opts = CompressOptions {
+                level,
+                strategy,
+                ..Default::default()
+            };
+            let mut compressed = deflate(&data, None, &opts);
+            compressed.extend_from_slice(b"trailer");
+            let expected = inflate_whole(&compressed, None);
+
+            for (input_size, output_size) in [(1, 1), (1, 4096), (7, 3), (100000, 100000)] {
+                let mut inflater = Inflater::new();
+                let decoded =
+                    inflate_in_pieces(&mut inflater, &compressed, input_size, output_size);
+                assert!(decoded == expected, "{:?} {}", level, input_size);
+                assert!(inflater.is_done());
+            }
+        }
+    }
+
+    #[test]
+    fn stops_at_end_of_stream() {
+        // a fixed block ending in the middle of its third byte
+        let compressed = [0x4b, 0x04, 0x00, 0xff];
+        let expected = inflate_whole(&compressed, None);
+        assert_eq!(expected, (b"a".to_vec(), 3));
+        for input_size in [1, 2, 4] {
+            let mut inflater = Inflater::new();
+            let decoded = inflate_in_pieces(&mut inflater, &compressed, input_size, 1);
+            assert_eq!(decoded, expected, "{}", input_size);
+        }
+
+        // nothing more is taken once done
+        let mut inflater = Inflater::new();
+        let mut output = [0; 10];
+        inflater.inflate(&compressed, &mut output).unwrap();
+        let progress = inflater.inflate(b"more", &mut output).unwrap();
+        assert_eq!(progress.status, InflateStatus::Done);
+        assert_eq!((progress.consumed, progress.written), (0, 0));
+    }
+
+    #[test]
+    fn dictionary_is_used() {
+        let data = sample(1000);
+        let dictionary = Dictionary::new(&data[..5000]);
+        let compressed = deflate(&data[5000..8000], Some(&dictionary), &Default::default());
+
+        let mut inflater = Inflater::with_dictionary(&dictionary);
+        let decoded = inflate_in_pieces(&mut inflater, &compressed, 3, 5);
+        assert_eq!(decoded, inflate_whole(&compressed, Some(&dictionary)));
+
+        let mut output = vec![0; 3000];
+        let err = Inflater::new()
+            .inflate(&compressed, &mut output)
+            .unwrap_err();
+        assert!(err.to_string().contains("invalid distance"), "{}", err);
+    }
+
+    #[test]
+    fn errors_are_reported() {
+        let mut output = [0; 10];
+        // block type 11
+        let err = Inflater::new().inflate(&[0x07], &mut output).unwrap_err();
+        assert!(err.to_string().contains("reserved"), "{}", err);
+
+        // stored block with NLEN not matching LEN
+        let err = Inflater::new()
+            .inflate(&[0x01, 0x05, 0x00, 0xfa, 0xfe], &mut output)
+            .unwrap_err();
+        assert!(err.to_string().contains("NLEN"), "{}", err);
+    }
+}
@@ -1,12 +1,12 @@
 use crate::reader::Reader;
-use crate::writer::Writer;
 use anyhow::{bail, Result};
-use std::io::{Read, Write};
+use std::io::BufRead;
 
-pub fn decompress<R, W>(reader: &mut Reader<R>, writer: &mut Writer<W>) -> Result<usize>
+// reads LEN and NLEN of a stored block; returns the number of bytes that
+// follow
+pub fn read_header<R>(reader: &mut Reader<R>) -> Result<usize>
 where
-    R: Read,
-    W: Write,
+    R: BufRead,
 {
     // TODO: check unread bits if any
     let len = u16::from_le_bytes([reader.next_byte()?, reader.next_byte()?]);
@@ -21,9 +21,5 @@ where
         );
     }
 
-    let len = len.into();
-
-    writer.copy_from(reader, len)?;
-
-    Ok(len)
+    Ok(len.into())
 }
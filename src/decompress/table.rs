@@ -0,0 +1,311 @@
+// Table-driven Huffman decoding in the manner of zlib and libdeflate. Codes up
+// to `primary_bits` long are decoded with a single lookup of the next bits in
+// the primary table, where each code fills every entry whose index starts with
+// it. Entries for longer codes point to a subtable indexed by the bits that
+// follow. DEFLATE packs codes starting from the most significant bit while
+// other data starts from the least significant one, so indices are the codes
+// with their bits reversed.
+//
+// Each entry also carries the base value and the number of extra bits of
+// lengths and distances, so that a whole length or distance is decoded at
+// once.
+
+use super::huffman::{DIST_INFO, LENGTH_INFO};
+use anyhow::{bail, ensure, Result};
+
+const MAX_CODE_LENGTH: usize = 15;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// a literal byte or a code length; `value` is the symbol itself
+    Symbol,
+    /// a length or a distance; `value` is the base to add the extra bits to
+    Base,
+    EndOfBlock,
+    /// a symbol that has a code but must not occur, e.g. 286 and 287 of the
+    /// literal/length alphabet; `value` is the symbol
+    Invalid,
+    /// no code starts with the bits
+    Unassigned,
+    /// `value` is the offset of the subtable and `extra` the number of bits
+    /// indexing it
+    Subtable,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Entry {
+    pub kind: Kind,
+    /// length of the code, including the primary bits for entries in
+    /// subtables
+    pub length: u8,
+    pub extra: u8,
+    pub value: u16,
+}
+
+impl Entry {
+    /// bits taken by the code and its extra bits
+    pub fn bits(&self) -> u32 {
+        u32::from(self.length) + u32::from(self.extra)
+    }
+}
+
+/// alphabets of DEFLATE, which tell what the symbols stand for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Alphabet {
+    CodeLength,
+    LiteralLength,
+    Distance,
+}
+
+impl Alphabet {
+    // zlib uses 9 and 6 bits for literal/length and distance codes; code length
+    // codes are at most 7 bits long
+    fn primary_bits(self) -> u8 {
+        match self {
+            Self::CodeLength => 7,
+            Self::LiteralLength => 9,
+            Self::Distance => 6,
+        }
+    }
+
+    fn entry(self, symbol: usize, length: u8) -> Entry {
+        let (kind, extra, value) = match (self, symbol) {
+            (Self::CodeLength, _) | (Self::LiteralLength, 0..=255) => {
+                (Kind::Symbol, 0, symbol as u16)
+            }
+            (Self::LiteralLength, 256) => (Kind::EndOfBlock, 0, 0),
+            (Self::LiteralLength, 257..=285) => {
+                let (extra, base) = LENGTH_INFO[symbol - 257];
+                (Kind::Base, extra, base as u16)
+            }
+            (Self::Distance, 0..=29) => {
+                let (extra, base) = DIST_INFO[symbol];
+                (Kind::Base, extra, base as u16)
+            }
+            _ => (Kind::Invalid, 0, symbol as u16),
+        };
+        Entry {
+            kind,
+            length,
+            extra,
+            value,
+        }
+    }
+}
+
+pub struct DecodeTable {
+    alphabet: Alphabet,
+    primary_bits: u8,
+    // the primary table followed by subtables
+    entries: Vec<Entry>,
+}
+
+impl DecodeTable {
+    /// builds the table of the canonical code given by the code length of each
+    /// symbol, 0 meaning the symbol is unused.
+    pub fn new(lengths: &[u8], alphabet: Alphabet) -> Result<Self> {
+        let mut counts = [0u16; MAX_CODE_LENGTH + 1];
+        for &length in lengths {
+            debug_assert!(usize::from(length) <= MAX_CODE_LENGTH);
+            counts[usize::from(length)] += 1;
+        }
+        counts[0] = 0;
+
+        // over-subscribed codes are ambiguous
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left = (left << 1) - i32::from(count);
+            ensure!(left >= 0, "over-subscribed code lengths");
+        }
+
+        let max_length = lengths.iter().copied().max().unwrap_or(0);
+        // as zlib, incomplete codes are allowed only for a single literal/length
+        // or distance symbol, e.g. a distance code of a block with one
+        // distance, and for no symbols at all
+        ensure!(
+            left == 0 || max_length == 0 || (alphabet != Alphabet::CodeLength && max_length == 1),
+            "incomplete code lengths",
+        );
+
+        let mut next_code = [0u16; MAX_CODE_LENGTH + 1];
+        for length in 2..=MAX_CODE_LENGTH {
+            next_code[length] = (next_code[length - 1] + counts[length - 1]) << 1;
+        }
+
+        let primary_bits = alphabet.primary_bits().min(max_length).max(1);
+        let primary_size = 1 << primary_bits;
+        let unassigned = |length| Entry {
+            kind: Kind::Unassigned,
+            length,
+            extra: 0,
+            value: 0,
+        };
+        let mut entries = vec![unassigned(primary_bits); primary_size];
+
+        // (symbol, length, reversed code) of codes longer than the primary bits
+        let mut long_codes = vec![];
+        // length of the longest code starting with each primary index
+        let mut longest = vec![0u8; primary_size];
+
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length == 0 {
+                continue;
+            }
+            let code = next_code[usize::from(length)];
+            next_code[usize::from(length)] += 1;
+            let reversed = (u32::from(code).reverse_bits() >> (32 - length)) as usize;
+
+            if length <= primary_bits {
+                let entry = alphabet.entry(symbol, length);
+                for index in (reversed..primary_size).step_by(1 << length) {
+                    entries[index] = entry;
+                }
+            } else {
+                let prefix = reversed & (primary_size - 1);
+                longest[prefix] = longest[prefix].max(length);
+                long_codes.push((symbol, length, reversed));
+            }
+        }
+
+        for (prefix, &length) in longest.iter().enumerate() {
+            if length == 0 {
+                continue;
+            }
+            let bits = length - primary_bits;
+            entries[prefix] = Entry {
+                kind: Kind::Subtable,
+                length: primary_bits,
+                extra: bits,
+                value: entries.len() as u16,
+            };
+            entries.resize(entries.len() + (1 << bits), unassigned(length));
+        }
+
+        for (symbol, length, reversed) in long_codes {
+            let subtable = entries[reversed & (primary_size - 1)];
+            let offset = usize::from(subtable.value);
+            let size = 1 << subtable.extra;
+            let entry = alphabet.entry(symbol, length);
+            let first = reversed >> primary_bits;
+            for index in (first..size).step_by(1 << (length - primary_bits)) {
+                entries[offset + index] = entry;
+            }
+        }
+
+        Ok(Self {
+            alphabet,
+            primary_bits,
+            entries,
+        })
+    }
+
+    /// the entry of the code at the start of `bits`, the next bit being the
+    /// least significant one
+    pub fn lookup(&self, bits: u64) -> Entry {
+        let entry = self.entries[(bits & ((1 << self.primary_bits) - 1)) as usize];
+        if entry.kind != Kind::Subtable {
+            return entry;
+        }
+        let index = (bits >> self.primary_bits) & ((1 << entry.extra) - 1);
+        self.entries[usize::from(entry.value) + index as usize]
+    }
+
+    /// the value of the symbol `entry`, looked up from `bits`, stands for;
+    /// base plus extra bits for lengths and distances. `entry.bits()` of
+    /// `bits` must be valid.
+    pub fn resolve(&self, entry: Entry, bits: u64) -> Result<usize> {
+        match entry.kind {
+            Kind::Symbol => Ok(usize::from(entry.value)),
+            Kind::EndOfBlock => Ok(0),
+            Kind::Base => {
+                let extra = (bits >> entry.length) & ((1 << entry.extra) - 1);
+                Ok(usize::from(entry.value) + extra as usize)
+            }
+            Kind::Invalid => match self.alphabet {
+                Alphabet::Distance => bail!("invalid distance code {}", entry.value),
+                _ => bail!("invalid literal/length code {}", entry.value),
+            },
+            // a subtable entry is never returned by `lookup`
+            Kind::Unassigned | Kind::Subtable => bail!("invalid Huffman code"),
+        }
+    }
+
+    /// decodes the code at the start of the lowest `available` bits of `bits`,
+    /// the rest being 0, along with its extra bits; returns the kind of the
+    /// symbol, its value as `resolve` does and the number of bits taken, or
+    /// None if more bits are needed.
+    pub fn decode(&self, bits: u64, available: u32) -> Result<Option<(Kind, usize, u32)>> {
+        // zeros beyond `available` still find the right entry as long as the
+        // code fits in the available bits
+        let entry = self.lookup(bits);
+        if entry.bits() > available {
+            return Ok(None);
+        }
+        let value = self.resolve(entry, bits)?;
+        Ok(Some((entry.kind, value, entry.bits())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decompress::huffman::{DIST_LENGTHS, LIT_LENGTHS};
+
+    // (kind, value, bits) decoded from the MSB-first `code` of `length` bits
+    // followed by `extra`
+    fn decode(table: &DecodeTable, code: u32, length: u8, extra: u64) -> (Kind, usize, u32) {
+        let reversed = u64::from(code.reverse_bits() >> (32 - length));
+        let bits = reversed | extra << length;
+        table.decode(bits, 64).unwrap().unwrap()
+    }
+
+    #[test]
+    fn fixed_codes_are_decoded() {
+        let lit = DecodeTable::new(&LIT_LENGTHS, Alphabet::LiteralLength).unwrap();
+        // literal 0 is 00110000, 144 is 110010000 and 256 is 0000000
+        assert_eq!(decode(&lit, 0b00110000, 8, 0), (Kind::Symbol, 0, 8));
+        assert_eq!(decode(&lit, 0b110010000, 9, 0), (Kind::Symbol, 144, 9));
+        assert_eq!(decode(&lit, 0, 7, 0), (Kind::EndOfBlock, 0, 7));
+        // 269 is 0001101 with 2 extra bits over 19
+        assert_eq!(decode(&lit, 0b0001101, 7, 0b11), (Kind::Base, 22, 9));
+
+        let dist = DecodeTable::new(&DIST_LENGTHS, Alphabet::Distance).unwrap();
+        // 29 is 11101 with 13 extra bits over 24577
+        assert_eq!(decode(&dist, 0b11101, 5, 8191), (Kind::Base, 32768, 18));
+        assert!(dist.decode(0b11111, 5).is_err());
+    }
+
+    #[test]
+    fn long_codes_go_to_subtables() {
+        // lengths 1, 2, ..., 14, 15, 15 make a complete code
+        let mut lengths: Vec<u8> = (1..=15).collect();
+        lengths.push(15);
+        let table = DecodeTable::new(&lengths, Alphabet::CodeLength).unwrap();
+        assert_eq!(decode(&table, 0, 1, 0), (Kind::Symbol, 0, 1));
+        assert_eq!(decode(&table, 0b1111110, 7, 0), (Kind::Symbol, 6, 7));
+        assert_eq!(decode(&table, 0b11111110, 8, 0), (Kind::Symbol, 7, 8));
+        assert_eq!(
+            decode(&table, 0b111111111111111, 15, 0),
+            (Kind::Symbol, 15, 15)
+        );
+
+        // too few bits for the code
+        assert!(table.decode(0b1111111, 7).unwrap().is_none());
+    }
+
+    #[test]
+    fn incomplete_and_over_subscribed_codes() {
+        let table = DecodeTable::new(&[0, 1], Alphabet::Distance).unwrap();
+        assert_eq!(decode(&table, 0, 1, 0), (Kind::Base, 2, 1));
+        assert!(table.decode(1, 1).is_err());
+
+        assert!(DecodeTable::new(&[1, 1, 1], Alphabet::CodeLength).is_err());
+
+        // only a single code of length 1 may be incomplete
+        assert!(DecodeTable::new(&[1], Alphabet::LiteralLength).is_ok());
+        assert!(DecodeTable::new(&[1], Alphabet::CodeLength).is_err());
+        assert!(DecodeTable::new(&[2, 2, 2], Alphabet::LiteralLength).is_err());
+        assert!(DecodeTable::new(&[2, 2, 2], Alphabet::Distance).is_err());
+        assert!(DecodeTable::new(&[0; 30], Alphabet::Distance).is_ok());
+    }
+}
@@ -0,0 +1,69 @@
+// preset dictionaries: data that primes the window before compression and
+// decompression so that even the first bytes can be matched, as zlib's FDICT.
+
+mod train;
+
+pub use train::{evaluate_dictionary, train_dictionary, HoldoutReport};
+
+use crate::adler32::adler32;
+use anyhow::{ensure, Result};
+
+/// preset dictionary along with its ID, Adler-32 of the whole dictionary.
+///
+/// Only the last 32 KiB can be referred to by matches. The compressor and the
+/// decompressor must use the same dictionary; raw DEFLATE streams don't record
+/// which one was used, so keep the ID alongside them and `verify` it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dictionary {
+    data: Vec<u8>,
+    id: u32,
+}
+
+impl Dictionary {
+    pub fn new(data: impl Into<Vec<u8>>) -> Self {
+        let data = data.into();
+        let id = adler32(&data);
+        Self { data, id }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// fails unless `id` is the ID of this dictionary.
+    pub fn verify(&self, id: u32) -> Result<()> {
+        ensure!(
+            id == self.id,
+            "wrong preset dictionary; expected ID = {:#010x}, ID of given dictionary = {:#010x}",
+            id,
+            self.id,
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn id_is_adler32() {
+        let dictionary = Dictionary::new(&b"Wikipedia"[..]);
+        assert_eq!(dictionary.id(), 0x11e60398);
+        assert!(dictionary.verify(0x11e60398).is_ok());
+        assert!(dictionary.verify(0x11e60399).is_err());
+    }
+
+    #[test]
+    fn id_covers_whole_dictionary() {
+        let data: Vec<u8> = (0..40000u32).map(|i| i as u8).collect();
+        let dictionary = Dictionary::new(data.clone());
+        assert_eq!(dictionary.id(), adler32(&data));
+        assert_ne!(dictionary.id(), adler32(&data[8192..]));
+        assert_eq!(dictionary.as_bytes(), &data[..]);
+    }
+}
@@ -0,0 +1,244 @@
+// Dictionary training after zstd's COVER algorithm: samples are cut into
+// segments, each scored by how many other samples share the k-mers it
+// contains, and the best segments are picked greedily until the dictionary is
+// full. Once a k-mer is in the dictionary it's worth nothing to further
+// segments, so near-duplicates aren't picked twice.
+
+use super::Dictionary;
+use crate::compress::deflate_raw;
+use crate::CompressOptions;
+use anyhow::{ensure, Result};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+// length of the substrings counted; 8 bytes make a `u64` key
+const KMER_LENGTH: usize = 8;
+// unit of the dictionary content
+const SEGMENT_LENGTH: usize = 48;
+// maximum distance is 32768
+const MAX_DICTIONARY_SIZE: usize = 32768;
+
+fn kmers(data: &[u8]) -> impl Iterator<Item = u64> + '_ {
+    data.windows(KMER_LENGTH)
+        .map(|w| u64::from_le_bytes(w.try_into().unwrap()))
+}
+
+// sum of the weights of distinct k-mers in `segment`
+fn score(segment: &[u8], weights: &HashMap<u64, u64>) -> u64 {
+    let mut seen = HashSet::new();
+    kmers(segment)
+        .filter(|&kmer| seen.insert(kmer))
+        .map(|kmer| weights.get(&kmer).copied().unwrap_or(0))
+        .sum()
+}
+
+/// builds a dictionary of at most `max_size` bytes out of `samples`, which
+/// should resemble the data to be compressed with it.
+///
+/// Substrings found in many samples are favored. The more valuable a part of
+/// the dictionary is, the closer it's placed to the end, since matches at
+/// short distances are cheaper to encode.
+pub fn train_dictionary<S>(samples: &[S], max_size: usize) -> Result<Dictionary>
+where
+    S: AsRef<[u8]>,
+{
+    ensure!(
+        max_size > 0 && max_size <= MAX_DICTIONARY_SIZE,
+        "dictionary size must be 1-{}; given {}",
+        MAX_DICTIONARY_SIZE,
+        max_size,
+    );
+
+    // number of samples each k-mer appears in
+    let mut weights = HashMap::new();
+    for sample in samples {
+        let distinct: HashSet<_> = kmers(sample.as_ref()).collect();
+        for kmer in distinct {
+            *weights.entry(kmer).or_insert(0u64) += 1;
+        }
+    }
+    // what appears in a single sample says nothing about the others
+    weights.retain(|_, count| *count > 1);
+
+    let segments: Vec<&[u8]> = samples
+        .iter()
+        .flat_map(|s| s.as_ref().chunks(SEGMENT_LENGTH))
+        .filter(|s| s.len() >= KMER_LENGTH)
+        .collect();
+
+    // scores only decrease as k-mers get covered, so a segment whose score is
+    // still up to date when it comes out on top is the best one (lazy greedy).
+    // ties go to the earlier segment for reproducibility.
+    let mut heap: BinaryHeap<_> = segments
+        .iter()
+        .enumerate()
+        .map(|(i, s)| (score(s, &weights), Reverse(i)))
+        .filter(|&(score, _)| score > 0)
+        .collect();
+
+    let mut picked = vec![];
+    let mut size = 0;
+    while let Some((stale, Reverse(i))) = heap.pop() {
+        if max_size - size < KMER_LENGTH {
+            break;
+        }
+
+        let segment = segments[i];
+        let current = score(segment, &weights);
+        if current < stale {
+            if current > 0 {
+                heap.push((current, Reverse(i)));
+            }
+            continue;
+        }
+        if segment.len() > max_size - size {
+            continue;
+        }
+
+        for kmer in kmers(segment) {
+            weights.remove(&kmer);
+        }
+        picked.push(segment);
+        size += segment.len();
+    }
+
+    // picked in the order of decreasing value; the best goes last
+    let mut data = Vec::with_capacity(size);
+    for segment in picked.iter().rev() {
+        data.extend_from_slice(segment);
+    }
+
+    Ok(Dictionary::new(data))
+}
+
+/// Sizes of a set of samples compressed individually with and without a
+/// dictionary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HoldoutReport {
+    pub samples: usize,
+    pub original_bytes: u64,
+    pub without_dictionary: u64,
+    pub with_dictionary: u64,
+}
+
+impl HoldoutReport {
+    /// compression ratio with the dictionary divided by that without it; above
+    /// 1 means the dictionary helps. None if the samples are all empty.
+    pub fn gain(&self) -> Option<f64> {
+        if self.original_bytes == 0 {
+            return None;
+        }
+        Some(self.without_dictionary as f64 / self.with_dictionary as f64)
+    }
+}
+
+/// compresses each of `samples`, which shouldn't have been used for training,
+/// into a raw DEFLATE stream with and without `dictionary` to estimate its
+/// effect.
+pub fn evaluate_dictionary<S>(
+    dictionary: &Dictionary,
+    samples: &[S],
+    opts: &CompressOptions,
+) -> Result<HoldoutReport>
+where
+    S: AsRef<[u8]>,
+{
+    let mut report = HoldoutReport {
+        samples: samples.len(),
+        original_bytes: 0,
+        without_dictionary: 0,
+        with_dictionary: 0,
+    };
+
+    for sample in samples {
+        let sample = sample.as_ref();
+        let mut compressed = vec![];
+        deflate_raw(&mut &sample[..], &mut compressed, None, opts)?;
+        report.without_dictionary += compressed.len() as u64;
+
+        compressed.clear();
+        deflate_raw(&mut &sample[..], &mut compressed, Some(dictionary), opts)?;
+        report.with_dictionary += compressed.len() as u64;
+
+        report.original_bytes += sample.len() as u64;
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // small JSON messages sharing keys and some values
+    fn samples() -> Vec<Vec<u8>> {
+        let statuses = ["active", "suspended", "pending_verification"];
+        (0..300u32)
+            .map(|i| {
+                let x = i.wrapping_mul(2654435761);
+                format!(
+                    "{{\"user_id\": {}, \"display_name\": \"user{}\", \"status\": \"{}\", \
+                     \"preferences\": {{\"theme\": \"dark\", \"notifications\": {}}}}}",
+                    x % 100000,
+                    x % 977,
+                    statuses[(x % 3) as usize],
+                    x % 2 == 0,
+                )
+                .into_bytes()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn dictionary_helps_unseen_samples() {
+        let samples = samples();
+        let (training, holdout) = samples.split_at(250);
+
+        let dictionary = train_dictionary(training, 4096).unwrap();
+        assert!(!dictionary.as_bytes().is_empty());
+        assert!(dictionary.as_bytes().len() <= 4096);
+
+        let report = evaluate_dictionary(&dictionary, holdout, &Default::default()).unwrap();
+        assert_eq!(report.samples, 50);
+        assert!(report.gain().unwrap() > 1.5, "{:?}", report);
+    }
+
+    #[test]
+    fn empty_samples_have_no_gain() {
+        let dictionary = train_dictionary(&samples(), 4096).unwrap();
+        for holdout in [&[][..], &[vec![], vec![]][..]] {
+            let report = evaluate_dictionary(&dictionary, holdout, &Default::default()).unwrap();
+            assert_eq!(report.original_bytes, 0);
+            assert_eq!(report.gain(), None);
+        }
+    }
+
+    #[test]
+    fn most_valuable_part_goes_last() {
+        // the first segment of every sample
+        let common = format!("{:<1$}", "common to all samples", SEGMENT_LENGTH);
+        let mut samples = vec![];
+        for i in 0..100 {
+            let mut sample = common.clone().into_bytes();
+            if i % 10 == 0 {
+                sample.extend_from_slice(b"in a tenth of them");
+            }
+            samples.push(sample);
+        }
+
+        let dictionary = train_dictionary(&samples, 1024).unwrap();
+        let data = dictionary.as_bytes();
+        assert!(data.ends_with(common.as_bytes()));
+        assert!(data.starts_with(b"in a tenth of them"));
+    }
+
+    #[test]
+    fn size_is_checked() {
+        assert!(train_dictionary(&samples(), 0).is_err());
+        assert!(train_dictionary(&samples(), 32769).is_err());
+        assert!(train_dictionary::<&[u8]>(&[], 100)
+            .unwrap()
+            .as_bytes()
+            .is_empty());
+    }
+}
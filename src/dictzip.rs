@@ -0,0 +1,350 @@
+// dictzip (.dz) as used by dictd: a single gzip member whose input is split
+// into chunks of the same length, each compressed independently and ended by a
+// full flush. The 'RA' extra subfield lists the compressed size of every chunk
+// so that a reader can decompress only the chunks covering the range it needs.
+//
+// 'RA' subfield data: VER (1), CHLEN (chunk length), CHCNT (number of chunks)
+// and CHCNT compressed chunk sizes, each a little endian 16-bit number.
+
+use crate::bit_writer::BitWriter;
+use crate::compress::{read_chunk, write_header, Deflater};
+use crate::crc32::Crc32;
+use crate::decompress::{read_header, Inflate};
+use crate::header::{ExtraSubfield, GzipHeader};
+use crate::reader::Reader;
+use crate::writer::Writer;
+use crate::CompressOptions;
+use anyhow::{bail, ensure, Context, Result};
+use std::io::{BufRead, Read, Seek, SeekFrom, Write};
+
+const VERSION: u16 = 1;
+// same as dictzip; even a stored chunk fits in 16 bits
+const CHUNK_LENGTH: usize = 58315;
+// the 'RA' subfield must fit in XLEN along with its own SI1, SI2 and LEN
+const MAX_CHUNKS: usize = (0xffff - 4 - 6) / 2;
+
+/// compresses everything `reader` yields into dictzip format.
+///
+/// Compressed chunks are kept in memory until the end of input as their sizes
+/// go into the header. `name` and `mtime` go into FNAME and MTIME of the
+/// header respectively; `threads` of the options is ignored.
+pub fn compress<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    name: Option<&str>,
+    mtime: u32,
+    opts: &CompressOptions,
+) -> Result<()>
+where
+    R: Read,
+    W: Write,
+{
+    let mut chunks = vec![];
+    let mut crc = Crc32::new();
+    let mut total_bytes = 0usize;
+
+    // we need to know whether a chunk is the last one before compressing it,
+    // so always read one chunk ahead.
+    let mut next = read_chunk(reader, CHUNK_LENGTH)?;
+    loop {
+        let current = std::mem::replace(&mut next, read_chunk(reader, CHUNK_LENGTH)?);
+        let last = next.is_empty();
+
+        crc.update(&current);
+        total_bytes = total_bytes.wrapping_add(current.len());
+
+        // a fresh deflater per chunk makes the flush a full flush
+        let mut deflater = Deflater::new(opts);
+        let mut chunk_writer = BitWriter::new(vec![]);
+        deflater.compress_block(&mut chunk_writer, &current, last)?;
+        if !last {
+            deflater.sync_flush(&mut chunk_writer)?;
+        }
+        chunks.push(chunk_writer.finish()?);
+
+        ensure!(
+            chunks.len() <= MAX_CHUNKS,
+            "dictzip: input is too large; at most {} bytes are supported",
+            MAX_CHUNKS * CHUNK_LENGTH,
+        );
+
+        if last {
+            break;
+        }
+    }
+
+    let mut data = Vec::with_capacity(6 + 2 * chunks.len());
+    data.extend_from_slice(&VERSION.to_le_bytes());
+    data.extend_from_slice(&(CHUNK_LENGTH as u16).to_le_bytes());
+    data.extend_from_slice(&(chunks.len() as u16).to_le_bytes());
+    for chunk in &chunks {
+        // at most CHUNK_LENGTH plus a few bytes
+        data.extend_from_slice(&(chunk.len() as u16).to_le_bytes());
+    }
+
+    let mut header_opts = opts.clone();
+    header_opts.extra_subfields.insert(
+        0,
+        ExtraSubfield {
+            id: ExtraSubfield::DICTZIP,
+            data,
+        },
+    );
+    write_header(writer, name, mtime, &header_opts)?;
+
+    for chunk in &chunks {
+        writer.write_all(chunk)?;
+    }
+
+    // CRC32, ISIZE
+    writer.write_all(&crc.sum().to_le_bytes())?;
+    writer.write_all(&((total_bytes & 0xffffffff) as u32).to_le_bytes())?;
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Random access reader of dictzip files.
+///
+/// Wrap `reader` with `BufReader` unless it's buffered already.
+pub struct DictzipReader<R> {
+    reader: R,
+    header: GzipHeader,
+    chunk_length: usize,
+    // offset of each chunk in the file, followed by the end of the last one
+    chunk_offsets: Vec<u64>,
+    // decompressed size
+    length: u64,
+}
+
+impl<R> DictzipReader<R>
+where
+    R: BufRead + Seek,
+{
+    /// reads the header at the current position of `reader`.
+    pub fn new(mut reader: R) -> Result<Self> {
+        // `Reader` consumes no more than the header, so `reader` ends up right
+        // after it
+        let mut header_reader = Reader::new(&mut reader);
+        let header = read_header(&mut header_reader)?;
+        header_reader.into_inner();
+        let data_offset = reader.stream_position()?;
+
+        let data = match header
+            .extra_subfields
+            .iter()
+            .find(|s| s.id == ExtraSubfield::DICTZIP)
+        {
+            Some(subfield) => &subfield.data,
+            None => bail!("dictzip: header has no 'RA' subfield"),
+        };
+        ensure!(data.len() >= 6, "dictzip: 'RA' subfield is too short");
+        let u16_at = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]);
+
+        let version = u16_at(0);
+        ensure!(
+            version == VERSION,
+            "dictzip: unsupported version {} (expected {})",
+            version,
+            VERSION,
+        );
+        let chunk_length = usize::from(u16_at(2));
+        let chunk_count = usize::from(u16_at(4));
+        ensure!(
+            data.len() == 6 + 2 * chunk_count,
+            "dictzip: 'RA' subfield lists {} chunks in {} bytes",
+            chunk_count,
+            data.len(),
+        );
+        ensure!(chunk_length > 0, "dictzip: chunk length is 0");
+
+        let mut chunk_offsets = vec![data_offset];
+        for i in 0..chunk_count {
+            let offset = chunk_offsets[i] + u64::from(u16_at(6 + 2 * i));
+            chunk_offsets.push(offset);
+        }
+
+        // ISIZE, which is modulo 2^32 but can't overflow as dictzip files are
+        // small enough
+        reader.seek(SeekFrom::Start(*chunk_offsets.last().unwrap() + 4))?;
+        let mut isize = [0; 4];
+        reader
+            .read_exact(&mut isize)
+            .context("dictzip: failed to read the trailer")?;
+        let length = u64::from(u32::from_le_bytes(isize));
+
+        // empty input still makes a chunk
+        let chunks_needed = std::cmp::max(length.div_ceil(chunk_length as u64), 1);
+        ensure!(
+            chunks_needed == chunk_count as u64,
+            "dictzip: {} chunks of {} bytes can't hold {} bytes",
+            chunk_count,
+            chunk_length,
+            length,
+        );
+
+        Ok(Self {
+            reader,
+            header,
+            chunk_length,
+            chunk_offsets,
+            length,
+        })
+    }
+
+    pub fn header(&self) -> &GzipHeader {
+        &self.header
+    }
+
+    /// decompressed size
+    pub fn len(&self) -> u64 {
+        self.length
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    /// decompresses `length` bytes starting at `offset` of the decompressed
+    /// data; fewer bytes are returned when the range goes past the end.
+    pub fn read_range(&mut self, offset: u64, length: usize) -> Result<Vec<u8>> {
+        let end = std::cmp::min(offset.saturating_add(length as u64), self.length);
+        if offset >= end {
+            return Ok(vec![]);
+        }
+
+        let chunk_length = self.chunk_length as u64;
+        let first = (offset / chunk_length) as usize;
+        let last = ((end - 1) / chunk_length) as usize;
+
+        let mut buf = vec![];
+        for chunk in first..=last {
+            self.read_chunk(chunk, &mut buf)?;
+        }
+
+        let start = (offset - first as u64 * chunk_length) as usize;
+        buf.drain(..start);
+        buf.truncate((end - offset) as usize);
+        Ok(buf)
+    }
+
+    // appends the decompressed `chunk`th chunk to `buf`
+    fn read_chunk(&mut self, chunk: usize, buf: &mut Vec<u8>) -> Result<()> {
+        let start = self.chunk_offsets[chunk];
+        let size = (self.chunk_offsets[chunk + 1] - start) as usize;
+        let expected = std::cmp::min(
+            self.chunk_length as u64,
+            self.length - chunk as u64 * self.chunk_length as u64,
+        ) as usize;
+
+        self.reader.seek(SeekFrom::Start(start))?;
+        let mut compressed = vec![0; size];
+        self.reader
+            .read_exact(&mut compressed)
+            .with_context(|| format!("dictzip: chunk {} is truncated", chunk))?;
+
+        let before = buf.len();
+        let mut reader = Reader::new(&compressed[..]);
+        // maximum distance is 32768
+        let mut writer = Writer::new(&mut *buf, 32768);
+        // stops before the flush that follows the chunk
+        Inflate::new()
+            .decode(&mut reader, &mut writer, expected)
+            .with_context(|| format!("dictzip: chunk {} is broken", chunk))?;
+        writer.flush()?;
+
+        ensure!(
+            buf.len() - before == expected,
+            "dictzip: chunk {} decompressed into {} bytes (expected {})",
+            chunk,
+            buf.len() - before,
+            expected,
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::sample;
+    use std::io::Cursor;
+
+    fn compress_with(data: &[u8], opts: &CompressOptions) -> Vec<u8> {
+        let mut compressed = vec![];
+        compress(&mut &data[..], &mut compressed, Some("dict"), 0, opts).unwrap();
+        compressed
+    }
+
+    #[test]
+    fn output_is_valid_gzip() {
+        let data = sample(30000);
+        let compressed = compress_with(&data, &Default::default());
+
+        let mut decompressed = vec![];
+        crate::GzDecoder::new(&compressed[..])
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, data);
+    }
+
+    #[test]
+    fn ranges_are_read() {
+        let data = sample(30000);
+        assert!(data.len() > 3 * CHUNK_LENGTH);
+
+        for level in [0, 1, 9] {
+            let opts = CompressOptions {
+                level: crate::CompressionLevel::new(level).unwrap(),
+                ..Default::default()
+            };
+            let compressed = compress_with(&data, &opts);
+            let mut reader = DictzipReader::new(Cursor::new(&compressed)).unwrap();
+            assert_eq!(reader.len(), data.len() as u64);
+            assert_eq!(reader.header().name.as_deref(), Some("dict"));
+
+            let ranges = [
+                (0, 10),
+                (CHUNK_LENGTH - 5, 10),
+                (CHUNK_LENGTH, CHUNK_LENGTH),
+                (1000, 3 * CHUNK_LENGTH),
+                (data.len() - 3, 100),
+            ];
+            for (offset, length) in ranges {
+                let end = std::cmp::min(offset + length, data.len());
+                assert_eq!(
+                    reader.read_range(offset as u64, length).unwrap(),
+                    &data[offset..end],
+                    "offset = {}, length = {}",
+                    offset,
+                    length,
+                );
+            }
+            assert!(reader.read_range(data.len() as u64, 1).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn empty_input_roundtrips() {
+        let compressed = compress_with(&[], &Default::default());
+        let mut reader = DictzipReader::new(Cursor::new(&compressed)).unwrap();
+        assert!(reader.is_empty());
+        assert!(reader.read_range(0, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn plain_gzip_is_rejected() {
+        let mut compressed = vec![];
+        crate::compress::compress(
+            &mut &b"hello"[..],
+            &mut compressed,
+            None,
+            0,
+            &Default::default(),
+        )
+        .unwrap();
+
+        assert!(DictzipReader::new(Cursor::new(&compressed)).is_err());
+    }
+}
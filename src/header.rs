@@ -0,0 +1,270 @@
+// gzip member header as specified in RFC 1952 section 2.3.
+
+use anyhow::{anyhow, ensure, Result};
+
+/// FLG byte of a gzip header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GzipFlags(pub u8);
+
+impl GzipFlags {
+    pub const TEXT: u8 = 0x01;
+    pub const HCRC: u8 = 0x02;
+    pub const EXTRA: u8 = 0x04;
+    pub const NAME: u8 = 0x08;
+    pub const COMMENT: u8 = 0x10;
+
+    pub fn is_text(&self) -> bool {
+        (self.0 & Self::TEXT) > 0
+    }
+
+    pub fn has_crc(&self) -> bool {
+        (self.0 & Self::HCRC) > 0
+    }
+
+    pub fn has_extra(&self) -> bool {
+        (self.0 & Self::EXTRA) > 0
+    }
+
+    pub fn has_name(&self) -> bool {
+        (self.0 & Self::NAME) > 0
+    }
+
+    pub fn has_comment(&self) -> bool {
+        (self.0 & Self::COMMENT) > 0
+    }
+}
+
+/// OS byte of a gzip header; the file system on which compression took place.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Os {
+    Fat,
+    Amiga,
+    Vms,
+    Unix,
+    VmCms,
+    AtariTos,
+    Hpfs,
+    Macintosh,
+    ZSystem,
+    CpM,
+    Tops20,
+    Ntfs,
+    Qdos,
+    AcornRiscos,
+    Unknown,
+    /// values not defined by RFC 1952
+    Undefined(u8),
+}
+
+impl Os {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Fat => "FAT filesystem",
+            Self::Amiga => "Amiga",
+            Self::Vms => "VMS",
+            Self::Unix => "Unix",
+            Self::VmCms => "VM/CMS",
+            Self::AtariTos => "Atari TOS",
+            Self::Hpfs => "HPFS filesystem",
+            Self::Macintosh => "Macintosh",
+            Self::ZSystem => "Z-System",
+            Self::CpM => "CP/M",
+            Self::Tops20 => "TOPS-20",
+            Self::Ntfs => "NTFS filesystem",
+            Self::Qdos => "QDOS",
+            Self::AcornRiscos => "Acorn RISCOS",
+            Self::Unknown => "unknown",
+            Self::Undefined(_) => "unknown (undefined value)",
+        }
+    }
+}
+
+impl From<u8> for Os {
+    fn from(byte: u8) -> Self {
+        match byte {
+            0 => Self::Fat,
+            1 => Self::Amiga,
+            2 => Self::Vms,
+            3 => Self::Unix,
+            4 => Self::VmCms,
+            5 => Self::AtariTos,
+            6 => Self::Hpfs,
+            7 => Self::Macintosh,
+            8 => Self::ZSystem,
+            9 => Self::CpM,
+            10 => Self::Tops20,
+            11 => Self::Ntfs,
+            12 => Self::Qdos,
+            13 => Self::AcornRiscos,
+            255 => Self::Unknown,
+            n => Self::Undefined(n),
+        }
+    }
+}
+
+impl From<Os> for u8 {
+    fn from(os: Os) -> Self {
+        match os {
+            Os::Fat => 0,
+            Os::Amiga => 1,
+            Os::Vms => 2,
+            Os::Unix => 3,
+            Os::VmCms => 4,
+            Os::AtariTos => 5,
+            Os::Hpfs => 6,
+            Os::Macintosh => 7,
+            Os::ZSystem => 8,
+            Os::CpM => 9,
+            Os::Tops20 => 10,
+            Os::Ntfs => 11,
+            Os::Qdos => 12,
+            Os::AcornRiscos => 13,
+            Os::Unknown => 255,
+            Os::Undefined(n) => n,
+        }
+    }
+}
+
+/// subfield of the extra field (FEXTRA), identified by two bytes SI1 and SI2.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtraSubfield {
+    pub id: [u8; 2],
+    pub data: Vec<u8>,
+}
+
+/// header of a gzip member.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GzipHeader {
+    pub flags: GzipFlags,
+    /// modification time of the original file in seconds since the Unix
+    /// epoch; 0 means no time stamp is available
+    pub mtime: u32,
+    /// XFL byte
+    pub extra_flags: u8,
+    pub os: Os,
+    /// contents of the extra field as is
+    pub extra: Option<Vec<u8>>,
+    /// subfields the extra field consists of
+    pub extra_subfields: Vec<ExtraSubfield>,
+    /// original file name as is, without the zero terminator
+    pub raw_name: Option<Vec<u8>>,
+    /// original file name decoded as ISO 8859-1 (LATIN-1)
+    pub name: Option<String>,
+    /// file comment as is, without the zero terminator
+    pub raw_comment: Option<Vec<u8>>,
+    /// file comment decoded as ISO 8859-1 (LATIN-1)
+    pub comment: Option<String>,
+    /// CRC-16 of the header, which has been verified
+    pub header_crc: Option<u16>,
+}
+
+impl ExtraSubfield {
+    /// 'RA': random access information of dictzip
+    pub const DICTZIP: [u8; 2] = *b"RA";
+    /// 'BC': block size of BGZF
+    pub const BGZF: [u8; 2] = *b"BC";
+}
+
+// splits the extra field into subfields, each of which is SI1, SI2, LEN and
+// LEN bytes of data; they must fill the field exactly
+pub(crate) fn parse_subfields(mut extra: &[u8]) -> Result<Vec<ExtraSubfield>> {
+    let xlen = extra.len();
+    let mut subfields = vec![];
+    while !extra.is_empty() {
+        let offset = xlen - extra.len();
+        ensure!(
+            extra.len() >= 4,
+            "extra field: truncated subfield header at offset {} (XLEN = {})",
+            offset,
+            xlen,
+        );
+        let id = [extra[0], extra[1]];
+        let length = usize::from(u16::from_le_bytes([extra[2], extra[3]]));
+        ensure!(
+            extra.len() - 4 >= length,
+            "extra field: subfield {:?} at offset {} needs {} bytes but only {} remain (XLEN = {})",
+            String::from_utf8_lossy(&id),
+            offset,
+            length,
+            extra.len() - 4,
+            xlen,
+        );
+        subfields.push(ExtraSubfield {
+            id,
+            data: extra[4..4 + length].to_vec(),
+        });
+        extra = &extra[4 + length..];
+    }
+    Ok(subfields)
+}
+
+// inverse of `parse_subfields`
+pub(crate) fn encode_subfields(subfields: &[ExtraSubfield]) -> Result<Vec<u8>> {
+    let mut extra = vec![];
+    for subfield in subfields {
+        let length = u16::try_from(subfield.data.len()).map_err(|_| {
+            anyhow!(
+                "extra field: subfield {:?} is too long; {} bytes",
+                String::from_utf8_lossy(&subfield.id),
+                subfield.data.len(),
+            )
+        })?;
+        extra.extend_from_slice(&subfield.id);
+        extra.extend_from_slice(&length.to_le_bytes());
+        extra.extend_from_slice(&subfield.data);
+    }
+    ensure!(
+        extra.len() <= 0xffff,
+        "extra field is too long; {} bytes",
+        extra.len(),
+    );
+    Ok(extra)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn os_roundtrips_through_byte() {
+        for byte in 0..=255u8 {
+            assert_eq!(u8::from(Os::from(byte)), byte);
+        }
+        assert_eq!(Os::from(3), Os::Unix);
+        assert_eq!(Os::from(200), Os::Undefined(200));
+    }
+
+    #[test]
+    fn subfields_are_split() {
+        let extra = [b'A', b'B', 2, 0, 1, 2, b'C', b'D', 0, 0];
+        let subfields = parse_subfields(&extra).unwrap();
+        assert_eq!(
+            subfields,
+            [
+                ExtraSubfield {
+                    id: *b"AB",
+                    data: vec![1, 2],
+                },
+                ExtraSubfield {
+                    id: *b"CD",
+                    data: vec![],
+                },
+            ]
+        );
+
+        assert_eq!(encode_subfields(&subfields).unwrap(), extra);
+
+        // lengths don't add up to XLEN
+        assert!(parse_subfields(&extra[..5]).is_err());
+        assert!(parse_subfields(&extra[..7]).is_err());
+    }
+
+    #[test]
+    fn too_long_subfield_is_rejected() {
+        let subfield = ExtraSubfield {
+            id: *b"XY",
+            data: vec![0; 0x10000],
+        };
+        assert!(encode_subfields(&[subfield]).is_err());
+    }
+}
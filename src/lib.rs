@@ -1,13 +1,35 @@
+mod adler32;
+mod bgzf;
+mod bit_writer;
+mod compress;
+mod crc32;
 mod decompress;
+mod dictionary;
+mod dictzip;
+mod header;
 mod reader;
-mod ring_buffer;
-mod tree;
+#[cfg(test)]
+mod test_util;
 mod writer;
+mod zlib;
 
-use anyhow::Result;
+pub use adler32::{adler32, Adler32};
+pub use bgzf::{virtual_offset, BgzfReader, BgzfWriter, GziIndex};
+pub use compress::{deflate_raw, GzEncoder, RawEncoder};
+pub use crc32::{crc32_combine, Crc32};
+pub use decompress::{
+    inflate_raw, GzDecoder, InflateProgress, InflateStats, InflateStatus, Inflater, RawDecoder,
+};
+pub use dictionary::{evaluate_dictionary, train_dictionary, Dictionary, HoldoutReport};
+pub use dictzip::DictzipReader;
+pub use header::{ExtraSubfield, GzipFlags, GzipHeader, Os};
+pub use zlib::{compress as zlib_compress, decompress as zlib_decompress};
+
+use anyhow::{ensure, Context, Result};
 use std::fs::File;
-use std::io::{sink, BufReader, BufWriter};
-use std::path::Path;
+use std::io::{sink, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
 
 #[derive(Debug)]
 pub struct DecompressOptions {
@@ -15,19 +37,228 @@ pub struct DecompressOptions {
     pub no_emit: bool,
 }
 
-/// decompresses gzip file at `src` into `dest`
-pub fn decompress_file(src: &Path, dest: Option<&Path>, opts: DecompressOptions) -> Result<()> {
+/// speed/ratio trade-off of compression, following gzip's `-1` ... `-9`.
+///
+/// Level 0 stores data without compressing it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompressionLevel(u8);
+
+impl CompressionLevel {
+    pub const NONE: Self = Self(0);
+    pub const FASTEST: Self = Self(1);
+    pub const BEST: Self = Self(9);
+
+    pub fn new(level: u8) -> Result<Self> {
+        ensure!(level <= 9, "compression level must be 0-9; given {}", level);
+        Ok(Self(level))
+    }
+
+    pub fn level(&self) -> u8 {
+        self.0
+    }
+}
+
+impl Default for CompressionLevel {
+    fn default() -> Self {
+        Self(6)
+    }
+}
+
+/// tuning for particular kinds of input, after zlib's strategies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Strategy {
+    #[default]
+    Default,
+    /// favors Huffman coding over short matches; for data consisting of small
+    /// values with somewhat random distribution
+    Filtered,
+    /// no string matching at all
+    HuffmanOnly,
+    /// matches of distance 1 only; for data such as PNG images
+    Rle,
+    /// never uses dynamic Huffman codes
+    Fixed,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct CompressOptions {
+    /// do not save the original file name and modification time
+    pub no_name: bool,
+    pub level: CompressionLevel,
+    pub strategy: Strategy,
+    /// compresses as much as possible with optimal parsing, refining the
+    /// parse of each block up to this many times. Far slower than any level;
+    /// `level` and `strategy` are ignored when set.
+    pub ultra: Option<u32>,
+    /// number of threads to compress with; values below 2 compress on the
+    /// calling thread. The output is the same for any number above 1.
+    pub threads: usize,
+    /// writes CRC-16 of the header (FHCRC)
+    pub header_crc: bool,
+    /// subfields to write into the extra field (FEXTRA) of the header
+    pub extra_subfields: Vec<ExtraSubfield>,
+}
+
+// converts errors for `Read`/`Write` implementations, keeping I/O errors as is
+fn into_io_error(err: anyhow::Error) -> std::io::Error {
+    match err.downcast::<std::io::Error>() {
+        Ok(err) => err,
+        Err(err) => std::io::Error::other(err),
+    }
+}
+
+/// decompresses gzip file at `src` into `dest`; returns the header of each
+/// member
+pub fn decompress_file(
+    src: &Path,
+    dest: Option<&Path>,
+    opts: DecompressOptions,
+) -> Result<Vec<GzipHeader>> {
     let mut reader = BufReader::new(File::open(src)?);
 
-    if opts.no_emit {
+    let headers = if opts.no_emit {
         let mut writer = sink();
-        decompress::decompress(&mut reader, &mut writer, &opts)?;
+        decompress::decompress(&mut reader, &mut writer, &opts)?
+    } else {
+        // `dest` is guaranteed to be Some by clap
+        debug_assert!(dest.is_some());
+        let mut writer = BufWriter::new(File::create(dest.unwrap())?);
+        decompress::decompress(&mut reader, &mut writer, &opts)?
+    };
+
+    Ok(headers)
+}
+
+// file name and modification time of `src` to go into the header
+fn name_and_mtime(src: &Path, file: &File, opts: &CompressOptions) -> (Option<String>, u32) {
+    if opts.no_name {
+        (None, 0)
+    } else {
+        // MTIME = 0 means no time stamp is available
+        let mtime = file
+            .metadata()
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_secs() as u32);
+        let name = src.file_name().map(|n| n.to_string_lossy().into_owned());
+        (name, mtime)
+    }
+}
+
+/// compresses file at `src` into gzip file at `dest`
+pub fn compress_file(src: &Path, dest: &Path, opts: CompressOptions) -> Result<()> {
+    let file = File::open(src)?;
+    let (name, mtime) = name_and_mtime(src, &file, &opts);
+
+    let mut reader = BufReader::new(file);
+    let mut writer = BufWriter::new(File::create(dest)?);
+    compress::compress(&mut reader, &mut writer, name.as_deref(), mtime, &opts)?;
+
+    Ok(())
+}
+
+/// compresses file at `src` into BGZF file at `dest`; writes the `.gzi` index
+/// to `index` if given
+pub fn compress_bgzf_file(
+    src: &Path,
+    dest: &Path,
+    index: Option<&Path>,
+    opts: CompressOptions,
+) -> Result<()> {
+    let mut reader = BufReader::new(File::open(src)?);
+    let mut writer = BgzfWriter::new(BufWriter::new(File::create(dest)?), opts);
+    std::io::copy(&mut reader, &mut writer)?;
+    let (_, gzi) = writer.finish()?;
+
+    if let Some(index) = index {
+        let mut writer = BufWriter::new(File::create(index)?);
+        gzi.write_to(&mut writer)?;
+        writer.flush()?;
+    }
+
+    Ok(())
+}
+
+/// compresses file at `src` into dictzip file at `dest`
+pub fn compress_dictzip_file(src: &Path, dest: &Path, opts: CompressOptions) -> Result<()> {
+    let file = File::open(src)?;
+    let (name, mtime) = name_and_mtime(src, &file, &opts);
+
+    let mut reader = BufReader::new(file);
+    let mut writer = BufWriter::new(File::create(dest)?);
+    dictzip::compress(&mut reader, &mut writer, name.as_deref(), mtime, &opts)?;
+
+    Ok(())
+}
+
+/// decompresses zlib stream at `src` into `dest`
+pub fn decompress_zlib_file(
+    src: &Path,
+    dest: Option<&Path>,
+    dictionary: Option<&Dictionary>,
+    opts: DecompressOptions,
+) -> Result<()> {
+    let mut reader = BufReader::new(File::open(src)?);
+
+    if opts.no_emit {
+        zlib::decompress(&mut reader, &mut sink(), dictionary)?;
     } else {
         // `dest` is guaranteed to be Some by clap
         debug_assert!(dest.is_some());
         let mut writer = BufWriter::new(File::create(dest.unwrap())?);
-        decompress::decompress(&mut reader, &mut writer, &opts)?;
+        zlib::decompress(&mut reader, &mut writer, dictionary)?;
     }
 
     Ok(())
 }
+
+/// compresses file at `src` into zlib stream at `dest`
+pub fn compress_zlib_file(
+    src: &Path,
+    dest: &Path,
+    dictionary: Option<&Dictionary>,
+    opts: CompressOptions,
+) -> Result<()> {
+    let mut reader = BufReader::new(File::open(src)?);
+    let mut writer = BufWriter::new(File::create(dest)?);
+    zlib::compress(&mut reader, &mut writer, dictionary, &opts)?;
+
+    Ok(())
+}
+
+/// trains a dictionary of at most `max_size` bytes on the files at `samples`
+/// and writes it to `dest`.
+///
+/// `holdout` percent of the samples, spread evenly, are left out of training
+/// to estimate the gain, which is returned unless no sample is left out.
+pub fn train_dictionary_file(
+    samples: &[PathBuf],
+    dest: &Path,
+    max_size: usize,
+    holdout: u8,
+) -> Result<Option<HoldoutReport>> {
+    ensure!(holdout < 100, "holdout must be 0-99%; given {}", holdout);
+
+    let mut training = vec![];
+    let mut held_out = vec![];
+    for (i, path) in samples.iter().enumerate() {
+        let data = std::fs::read(path).with_context(|| format!("failed to read {:?}", path))?;
+        let holdout = usize::from(holdout);
+        if i * holdout / 100 != (i + 1) * holdout / 100 {
+            held_out.push(data);
+        } else {
+            training.push(data);
+        }
+    }
+    ensure!(!training.is_empty(), "no samples to train on");
+
+    let dictionary = train_dictionary(&training, max_size)?;
+    std::fs::write(dest, dictionary.as_bytes())?;
+
+    if held_out.is_empty() {
+        return Ok(None);
+    }
+    let report = evaluate_dictionary(&dictionary, &held_out, &Default::default())?;
+    Ok(Some(report))
+}
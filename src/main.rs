@@ -1,12 +1,22 @@
 use anyhow::Result;
-use clap::Parser;
-use std::path::PathBuf;
+use clap::{Parser, Subcommand, ValueEnum};
+use std::path::{Path, PathBuf};
 
 #[derive(Parser)]
-#[command(author, version, about, long_about = None)]
+#[command(
+    author,
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands(true),
+    subcommand_negates_reqs(true)
+)]
 struct Opts {
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[arg(required(true))]
     /// Path for source file.
-    src: PathBuf,
+    src: Option<PathBuf>,
     #[clap(required_unless_present("no_emit"))]
     /// Path for destination file. Required unless `--no-emit` is specified.
     dest: Option<PathBuf>,
@@ -16,19 +26,218 @@ struct Opts {
     #[arg(long)]
     /// Do not emit decompressed content. <dest> would be ignored if specified.
     no_emit: bool,
+    #[arg(long, conflicts_with_all(["show_header", "no_emit"]))]
+    /// Compresses <src> into <dest> instead of decompressing.
+    compress: bool,
+    #[arg(long, requires("compress"))]
+    /// Do not save the original file name and modification time.
+    no_name: bool,
+    #[arg(long, requires("compress"), value_parser = clap::value_parser!(u8).range(0..=9))]
+    /// Compression level; 1 is the fastest and 9 compresses best. 0 stores data as is.
+    level: Option<u8>,
+    #[arg(long, requires("compress"), value_enum, default_value_t = Strategy::Default)]
+    /// Compression strategy.
+    strategy: Strategy,
+    #[arg(
+        long,
+        requires("compress"),
+        num_args(0..=1),
+        require_equals(true),
+        default_missing_value("15")
+    )]
+    /// Compresses as much as possible by optimal parsing with the given number
+    /// of iterations. Much slower; --level and --strategy are ignored.
+    ultra: Option<u32>,
+    #[arg(long, requires("compress"), default_value_t = 1)]
+    /// Number of threads to compress with.
+    threads: usize,
+    #[arg(long, requires("compress"))]
+    /// Protects the header with CRC-16 (FHCRC).
+    header_crc: bool,
+    #[arg(long, requires("compress"), value_parser = parse_subfield)]
+    /// Adds a subfield to the extra field of the header; two-letter ID and
+    /// hexadecimal data separated by a colon, e.g. XY:0a1b. May be repeated.
+    extra_subfield: Vec<my_gzip::ExtraSubfield>,
+    #[arg(long, requires("compress"), conflicts_with_all(["threads", "header_crc", "extra_subfield"]))]
+    /// Compresses into BGZF (blocked gzip) instead of a single member. The
+    /// header carries neither file name nor modification time.
+    bgzf: bool,
+    #[arg(long, requires("bgzf"))]
+    /// Writes the .gzi index of the BGZF output to <dest>.gzi as well.
+    index: bool,
+    #[arg(long, requires("compress"), conflicts_with_all(["threads", "bgzf"]))]
+    /// Compresses into dictzip (.dz) format, which allows random access.
+    dictzip: bool,
+    #[arg(
+        long,
+        conflicts_with_all(["show_header", "bgzf", "dictzip", "header_crc", "extra_subfield"])
+    )]
+    /// Reads or writes a zlib stream (RFC 1950) instead of gzip.
+    zlib: bool,
+    #[arg(long, requires("zlib"))]
+    /// Preset dictionary of the zlib stream.
+    dictionary: Option<PathBuf>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Builds a preset dictionary out of sample files and reports its effect
+    /// on the samples held out of training.
+    Train {
+        /// Path to write the dictionary to.
+        dest: PathBuf,
+        #[arg(required(true))]
+        /// Sample files resembling the data to be compressed.
+        samples: Vec<PathBuf>,
+        #[arg(long, default_value_t = 32768, value_parser = clap::value_parser!(u64).range(1..=32768))]
+        /// Maximum size of the dictionary in bytes.
+        size: u64,
+        #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u8).range(0..=99))]
+        /// Percentage of the samples left out of training to estimate the gain.
+        holdout: u8,
+    },
+}
+
+fn train(dest: &Path, samples: &[PathBuf], size: usize, holdout: u8) -> Result<()> {
+    let report = my_gzip::train_dictionary_file(samples, dest, size, holdout)?;
+    println!("dictionary: {} bytes", std::fs::metadata(dest)?.len());
+
+    match report {
+        Some(report) => {
+            let ratio = |compressed: u64| report.original_bytes as f64 / compressed as f64;
+            println!(
+                "holdout: {} samples, {} bytes",
+                report.samples, report.original_bytes
+            );
+            println!(
+                "  without dictionary: {} bytes (ratio {:.2})",
+                report.without_dictionary,
+                ratio(report.without_dictionary),
+            );
+            println!(
+                "  with dictionary:    {} bytes (ratio {:.2})",
+                report.with_dictionary,
+                ratio(report.with_dictionary),
+            );
+            match report.gain() {
+                Some(gain) => println!("  gain: {:.2}x", gain),
+                None => println!("  gain: none as the samples are empty"),
+            }
+        }
+        None => println!("holdout: no samples held out"),
+    }
+
+    Ok(())
+}
+
+fn parse_subfield(s: &str) -> Result<my_gzip::ExtraSubfield, String> {
+    let (id, hex) = s.split_once(':').ok_or("expected <ID>:<HEX>")?;
+    let id: [u8; 2] = id
+        .as_bytes()
+        .try_into()
+        .map_err(|_| "ID must be two bytes")?;
+    if !hex.is_ascii() || hex.len() % 2 != 0 {
+        return Err("data must consist of pairs of hex digits".into());
+    }
+    let data = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+        .collect::<Result<_, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(my_gzip::ExtraSubfield { id, data })
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Strategy {
+    Default,
+    Filtered,
+    HuffmanOnly,
+    Rle,
+    Fixed,
+}
+
+impl From<Strategy> for my_gzip::Strategy {
+    fn from(strategy: Strategy) -> Self {
+        match strategy {
+            Strategy::Default => Self::Default,
+            Strategy::Filtered => Self::Filtered,
+            Strategy::HuffmanOnly => Self::HuffmanOnly,
+            Strategy::Rle => Self::Rle,
+            Strategy::Fixed => Self::Fixed,
+        }
+    }
 }
 
 fn main() -> Result<()> {
     let opts = Opts::parse();
-    let src = opts.src.as_path();
+
+    if let Some(Command::Train {
+        dest,
+        samples,
+        size,
+        holdout,
+    }) = opts.command
+    {
+        return train(&dest, &samples, size as usize, holdout);
+    }
+
+    // `src` is guaranteed to be Some by clap without a subcommand
+    let src = opts.src.as_deref().unwrap();
     let dest = opts.dest.as_deref();
+    let dictionary = opts
+        .dictionary
+        .as_deref()
+        .map(std::fs::read)
+        .transpose()?
+        .map(my_gzip::Dictionary::new);
+
+    if opts.compress {
+        let level = match opts.level {
+            Some(level) => my_gzip::CompressionLevel::new(level)?,
+            None => Default::default(),
+        };
+        let compress_opts = my_gzip::CompressOptions {
+            no_name: opts.no_name,
+            level,
+            strategy: opts.strategy.into(),
+            ultra: opts.ultra,
+            threads: opts.threads,
+            header_crc: opts.header_crc,
+            extra_subfields: opts.extra_subfield,
+        };
+
+        // `dest` is guaranteed to be Some by clap as `--compress` conflicts
+        // with `--no-emit`
+        let dest = dest.unwrap();
+        if opts.bgzf {
+            let index = opts.index.then(|| {
+                let mut path = dest.as_os_str().to_owned();
+                path.push(".gzi");
+                PathBuf::from(path)
+            });
+            my_gzip::compress_bgzf_file(src, dest, index.as_deref(), compress_opts)?;
+        } else if opts.zlib {
+            my_gzip::compress_zlib_file(src, dest, dictionary.as_ref(), compress_opts)?;
+        } else if opts.dictzip {
+            my_gzip::compress_dictzip_file(src, dest, compress_opts)?;
+        } else {
+            my_gzip::compress_file(src, dest, compress_opts)?;
+        }
+
+        return Ok(());
+    }
 
+    let zlib = opts.zlib;
     let opts = my_gzip::DecompressOptions {
         show_header: opts.show_header,
         no_emit: opts.no_emit,
     };
 
-    my_gzip::decompress_file(src, dest, opts)?;
+    if zlib {
+        my_gzip::decompress_zlib_file(src, dest, dictionary.as_ref(), opts)?;
+    } else {
+        my_gzip::decompress_file(src, dest, opts)?;
+    }
 
     Ok(())
 }
@@ -1,38 +1,165 @@
-use anyhow::{anyhow, Result};
-use std::io::{Read, Write};
+// Bit reader over a `BufRead` with a 64-bit buffer. Bytes are copied from the
+// buffer of the underlying reader up to 8 at a time, but consumed from it only
+// once their bits are, so that whatever follows the data read is left there.
+
+use anyhow::Result;
+use std::io::{BufRead, ErrorKind, Read, Write};
 
 pub struct Reader<R> {
     reader: R,
-    current: u8,
-    pos: u8,
+    // bits loaded but not consumed yet, the next one being the least
+    // significant; the rest are 0
+    bits: u64,
+    count: u32,
+    // bytes at the start of the buffer of `reader` that have been loaded into
+    // `bits` but not consumed from `reader` yet
+    pending: usize,
+    // bytes loaded so far
+    loaded: u64,
 }
 
 impl<R> Reader<R>
 where
-    R: Read,
+    R: BufRead,
 {
     pub fn new(r: R) -> Self {
         Self {
             reader: r,
-            current: 0,
-            pos: 0,
+            bits: 0,
+            count: 0,
+            pending: 0,
+            loaded: 0,
         }
     }
 
-    pub fn next_bit(&mut self) -> Result<bool> {
-        if self.pos >= 8 {
-            return Err(anyhow!("finished"));
+    /// number of bits consumed so far. The underlying reader is left right
+    /// after the byte holding the last of them by `into_inner`, i.e. it has
+    /// given exactly `bit_position().div_ceil(8)` bytes, unless bits were
+    /// skipped to the byte boundary.
+    pub fn bit_position(&self) -> u64 {
+        self.loaded * 8 - u64::from(self.count)
+    }
+
+    pub fn into_inner(mut self) -> R {
+        self.sync();
+        self.reader
+    }
+
+    // consumes from `reader` the pending bytes whose bits have been consumed,
+    // even partially
+    fn sync(&mut self) {
+        let used = self.pending.saturating_sub((self.count / 8) as usize);
+        self.reader.consume(used);
+        self.pending -= used;
+    }
+
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        loop {
+            match self.reader.fill_buf() {
+                // reborrowing ends the loop; see rust-lang/rust#54663
+                Ok(_) => return self.reader.fill_buf(),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// loads as many bytes as fit in the buffer out of what the underlying
+    /// reader has buffered, without consuming them from it; returns the
+    /// number of bytes loaded
+    pub fn refill(&mut self) -> Result<usize> {
+        let room = ((64 - self.count) / 8) as usize;
+        if room == 0 {
+            return Ok(0);
+        }
+
+        self.sync();
+        let pending = self.pending;
+        let buf = self.fill_buf()?;
+        debug_assert!(buf.len() >= pending);
+        let fresh = &buf[pending..];
+        let amount = std::cmp::min(room, fresh.len());
+
+        let mut bytes = [0; 8];
+        bytes[..amount].copy_from_slice(&fresh[..amount]);
+        let word = u64::from_le_bytes(bytes);
+        // shifting by 64 is not allowed
+        if self.count < 64 {
+            self.bits |= word << self.count;
+        }
+        self.count += amount as u32 * 8;
+        self.pending += amount;
+        self.loaded += amount as u64;
+
+        Ok(amount)
+    }
+
+    // loads at least a byte unless at the end of input; returns whether it did
+    fn load_more(&mut self) -> Result<bool> {
+        if self.refill()? > 0 {
+            return Ok(true);
+        }
+        if self.count > 56 {
+            return Ok(false);
         }
 
-        let masked = self.current & (1 << self.pos);
-        let bit = masked > 0;
+        // everything `reader` has buffered is loaded already. Consuming it all
+        // is fine as more bits are needed than those loaded.
+        self.reader.consume(self.pending);
+        self.pending = 0;
+        Ok(self.refill()? > 0)
+    }
+
+    /// loads bytes until at least `n` bits are available or input runs out;
+    /// returns the number of bits available. Bytes are consumed from the
+    /// underlying reader only when the bits loaded so far run short, so `n`
+    /// must not be more than actually needed.
+    pub fn fill_bits(&mut self, n: u32) -> Result<u32> {
+        debug_assert!(n <= 56);
+        while self.count < n && self.load_more()? {}
+        Ok(self.count)
+    }
 
-        self.pos += 1;
-        if self.pos >= 8 {
-            self.read_next_byte()?;
+    /// loads at least `n` bits as `fill_bits` does; fails with `UnexpectedEof`
+    /// if input runs out first.
+    pub fn ensure_bits(&mut self, n: u32) -> Result<()> {
+        if self.fill_bits(n)? < n {
+            return Err(std::io::Error::from(ErrorKind::UnexpectedEof).into());
         }
+        Ok(())
+    }
 
-        Ok(bit)
+    /// bits loaded but not consumed yet and their number; the rest of the
+    /// returned bits are 0
+    pub fn buffered_bits(&self) -> (u64, u32) {
+        (self.bits, self.count)
+    }
+
+    /// the next `n` bits, up to 32, without consuming them
+    pub fn peek_bits(&mut self, n: u32) -> Result<u64> {
+        debug_assert!(n <= 32);
+        self.ensure_bits(n)?;
+        Ok(self.bits & ((1 << n) - 1))
+    }
+
+    /// consumes `n` of the loaded bits
+    pub fn consume(&mut self, n: u32) {
+        debug_assert!(n <= self.count);
+        // shifting by 64 is not allowed
+        self.bits = self.bits.checked_shr(n).unwrap_or(0);
+        self.count -= n;
+    }
+
+    /// reads `n` bits, up to 32, as a number whose least significant bit
+    /// comes first
+    pub fn read_bits(&mut self, n: u32) -> Result<u64> {
+        let value = self.peek_bits(n)?;
+        self.consume(n);
+        Ok(value)
+    }
+
+    pub fn next_bit(&mut self) -> Result<bool> {
+        Ok(self.read_bits(1)? == 1)
     }
 
     // returns the next byte.
@@ -41,22 +168,23 @@ where
     pub fn next_byte(&mut self) -> Result<u8> {
         self.ensure_byte_boundary()?;
 
-        let byte = self.current;
+        Ok(self.read_bits(8)? as u8)
+    }
 
-        self.read_next_byte()?;
+    // skips the remaining bits in the current byte if any
+    pub fn ensure_byte_boundary(&mut self) -> Result<()> {
+        self.consume(self.count % 8);
 
-        Ok(byte)
+        Ok(())
     }
 
-    pub fn ensure_byte_boundary(&mut self) -> Result<()> {
-        if self.pos == 0 {
-            return Ok(());
-        }
+    // returns whether any byte is left after the current one.
+    // Note that this function disregards any remaining bits in the current byte
+    // when current position isn't on the byte boundary.
+    pub fn has_more_bytes(&mut self) -> Result<bool> {
+        self.ensure_byte_boundary()?;
 
-        match self.read_next_byte()? {
-            Some(_) => Ok(()),
-            None => Err(anyhow!("no more bytes")),
-        }
+        Ok(self.fill_bits(8)? >= 8)
     }
 
     // reads from underlying reader to the given buffer; returns the bytes read.
@@ -66,84 +194,138 @@ where
     where
         W: Write,
     {
-        use std::io::ErrorKind;
-
         self.ensure_byte_boundary()?;
 
         let mut remain = length;
         let mut buf = [0; 8192];
 
-        'outer: while remain > 0 {
+        while remain > 0 {
             let buf = if remain < 8192 {
                 &mut buf[..remain]
             } else {
                 &mut buf
             };
-            loop {
-                match self.reader.read(buf) {
-                    Ok(0) => break 'outer,
-                    Ok(bytes) => {
-                        writer.write_all(buf)?;
-                        remain -= bytes;
-                        break;
-                    }
-                    Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
-                    Err(e) => return Err(e.into()),
-                }
+            let bytes = self.read(buf)?;
+            if bytes == 0 {
+                break;
             }
+            writer.write_all(&buf[..bytes])?;
+            remain -= bytes;
         }
 
-        self.read_next_byte()?;
-
         Ok(length - remain)
     }
+}
 
-    pub fn skip(&mut self, length: usize) -> Result<usize> {
-        self.ensure_byte_boundary()?;
-
-        self.copy_to(&mut std::io::sink(), length)
-    }
+impl<R> Read for Reader<R>
+where
+    R: BufRead,
+{
+    // discards the rest of a partially consumed byte
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
 
-    fn read_next_byte(&mut self) -> std::io::Result<Option<()>> {
-        use std::io::ErrorKind;
+        self.consume(self.count % 8);
+        if self.count > 0 {
+            // whole bytes loaded already
+            let amount = std::cmp::min(buf.len(), (self.count / 8) as usize);
+            for b in &mut buf[..amount] {
+                *b = self.bits as u8;
+                self.consume(8);
+            }
+            return Ok(amount);
+        }
 
-        // taken from `Iterator` impl for `std::io::Bytes`
+        // nothing is loaded, so every pending byte has been consumed
+        self.sync();
         loop {
-            return match self.reader.read(std::slice::from_mut(&mut self.current)) {
-                Ok(0) => {
-                    self.pos = 8;
-                    Ok(None)
-                }
-                Ok(..) => {
-                    self.pos = 0;
-                    Ok(Some(()))
+            match self.reader.read(buf) {
+                Ok(amount) => {
+                    self.loaded += amount as u64;
+                    return Ok(amount);
                 }
                 Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
-                Err(e) => Err(e),
-            };
+                Err(e) => return Err(e),
+            }
         }
     }
 }
 
-impl<R> Read for Reader<R>
-where
-    R: Read,
-{
-    // discards partially read `self.current` (i.e. when `self.pos > 0`)
-    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        if buf.is_empty() {
-            return Ok(0);
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufReader;
 
-        let amount = if self.pos == 0 {
-            buf[0] = self.current;
-            self.reader.read(&mut buf[1..])? + 1
-        } else {
-            self.reader.read(buf)?
-        };
+    #[test]
+    fn bits_and_bytes_interleave() {
+        let mut reader = Reader::new(&[0b1010_0101, 0x12, 0x34, 0x56][..]);
 
-        self.read_next_byte()?;
+        assert!(reader.next_bit().unwrap());
+        assert!(!reader.next_bit().unwrap());
+        assert_eq!(reader.bit_position(), 2);
+        // the rest of the first byte is skipped
+        assert_eq!(reader.next_byte().unwrap(), 0x12);
+        assert_eq!(reader.bit_position(), 16);
+        assert!(!reader.next_bit().unwrap());
+        assert_eq!(reader.bit_position(), 17);
 
-        Ok(amount)
+        let mut buf = vec![];
+        assert_eq!(reader.copy_to(&mut buf, 10).unwrap(), 1);
+        assert_eq!(buf, [0x56]);
+        assert_eq!(reader.bit_position(), 32);
+        assert!(!reader.has_more_bytes().unwrap());
+        assert!(reader.next_byte().is_err());
+    }
+
+    #[test]
+    fn copy_to_includes_peeked_byte() {
+        let mut reader = Reader::new(&[1, 2, 3, 4][..]);
+
+        assert_eq!(reader.next_byte().unwrap(), 1);
+        assert!(reader.has_more_bytes().unwrap());
+
+        let mut buf = vec![];
+        assert_eq!(reader.copy_to(&mut buf, 2).unwrap(), 2);
+        assert_eq!(buf, [2, 3]);
+        assert_eq!(reader.next_byte().unwrap(), 4);
+    }
+
+    #[test]
+    fn peek_does_not_consume() {
+        let mut reader = Reader::new(&[0b1100_0101, 0b0000_0011, 0xff][..]);
+
+        assert_eq!(reader.peek_bits(3).unwrap(), 0b101);
+        assert_eq!(reader.peek_bits(10).unwrap(), 0b11_1100_0101);
+        reader.consume(3);
+        assert_eq!(reader.read_bits(7).unwrap(), 0b11_11000);
+        assert_eq!(reader.bit_position(), 10);
+        assert_eq!(reader.read_bits(14).unwrap(), 0x3fc0);
+        assert!(reader.peek_bits(1).is_err());
+    }
+
+    #[test]
+    fn only_bytes_whose_bits_are_consumed_are_consumed() {
+        let data: Vec<u8> = (0..100).collect();
+        let mut input = &data[..];
+        let mut reader = Reader::new(&mut input);
+
+        assert_eq!(reader.read_bits(3).unwrap(), 0);
+        // loaded 8 bytes ahead but only the first one is used
+        assert_eq!(reader.buffered_bits().1, 61);
+        assert_eq!(reader.bit_position(), 3);
+        assert_eq!(reader.into_inner()[0], 1);
+
+        // bulk refills across the boundaries of a small buffer
+        let mut input = BufReader::with_capacity(5, &data[..]);
+        let mut reader = Reader::new(&mut input);
+        for i in 0..40u64 {
+            assert_eq!(reader.read_bits(16).unwrap(), ((i * 2 + 1) << 8) | (i * 2));
+        }
+        assert_eq!(reader.read_bits(4).unwrap(), 0);
+        let mut rest = vec![];
+        reader.into_inner().read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, &data[81..]);
     }
 }
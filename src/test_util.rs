@@ -0,0 +1,11 @@
+// Helpers shared by tests.
+
+/// `lines` lines of text, each a number repeating every 1013 lines followed
+/// by a pseudo-random one, so that the data compresses moderately.
+pub fn sample(lines: u32) -> Vec<u8> {
+    let mut data = vec![];
+    for i in 0..lines {
+        data.extend_from_slice(format!("{} {}\n", i % 1013, i.wrapping_mul(2654435761)).as_bytes());
+    }
+    data
+}
@@ -1,49 +1,219 @@
-use crate::ring_buffer::RingBuffer;
-use anyhow::Result;
+// Output of decompression. Decoded bytes are gathered in a contiguous buffer
+// that also serves as the history window for matches, and are written to the
+// underlying writer in large chunks. When the buffer fills up, the last
+// `window_size` bytes are moved to its front and decoding goes on after them.
+
+use crate::crc32::Crc32;
+use anyhow::{ensure, Result};
 use std::io::{Read, Write};
 
+// bytes gathered in addition to the window before they're written out
+const CHUNK_SIZE: usize = 128 * 1024;
+
 pub struct Writer<W> {
     writer: W,
-    ringbuf: RingBuffer<u8>,
+    // history followed by bytes not written yet
+    buf: Vec<u8>,
+    // bytes at the start of `buf` already written, or those of a dictionary
+    flushed: usize,
+    window_size: usize,
+    // CRC-32 of everything written so far
+    crc: Crc32,
 }
 
 impl<W> Writer<W> {
-    pub fn new(writer: W, buf_size: usize) -> Self {
+    pub fn new(writer: W, window_size: usize) -> Self {
+        assert!(window_size > 0);
+
         Self {
             writer,
-            ringbuf: RingBuffer::new(buf_size),
+            buf: Vec::with_capacity(window_size + CHUNK_SIZE),
+            flushed: 0,
+            window_size,
+            crc: Crc32::new(),
         }
     }
+
+    /// the underlying writer, which doesn't have the bytes not flushed yet
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// CRC-32 of the bytes produced so far, flushed or not
+    pub fn crc32(&self) -> u32 {
+        let mut crc = self.crc;
+        crc.update(&self.buf[self.flushed..]);
+        crc.sum()
+    }
+
+    /// makes the tail of `dictionary` available to `copy_within` as if it
+    /// preceded the output, without writing it.
+    pub fn set_dictionary(&mut self, dictionary: &[u8]) {
+        let start = dictionary.len().saturating_sub(self.window_size);
+        self.buf.extend_from_slice(&dictionary[start..]);
+        self.flushed = self.buf.len();
+    }
+
+    // bytes `copy_within` can refer to
+    fn history_len(&self) -> usize {
+        std::cmp::min(self.buf.len(), self.window_size)
+    }
 }
 
 impl<W> Writer<W>
 where
     W: Write,
 {
+    /// starts over for the next gzip member, which is a separate DEFLATE
+    /// stream: CRC-32 restarts and matches can't refer to the output so far.
+    pub fn reset(&mut self) -> Result<()> {
+        self.write_out()?;
+        self.buf.clear();
+        self.flushed = 0;
+        self.crc = Crc32::new();
+        Ok(())
+    }
+
     pub fn copy_from<R>(&mut self, reader: &mut R, length: usize) -> Result<()>
     where
         R: Read,
     {
-        let (first, second) = self.ringbuf.copy_from(reader, length)?;
-        self.writer.write_all(first)?;
-        self.writer.write_all(second)?;
+        // stored blocks may be longer than the buffer
+        let mut remain = length;
+        while remain > 0 {
+            self.make_room(1)?;
+            let start = self.buf.len();
+            let chunk = std::cmp::min(remain, self.buf.capacity() - start);
+            self.buf.resize(start + chunk, 0);
+            if let Err(e) = reader.read_exact(&mut self.buf[start..]) {
+                self.buf.truncate(start);
+                return Err(e.into());
+            }
+            remain -= chunk;
+        }
         Ok(())
     }
 
     pub fn copy_within(&mut self, distance: usize, length: usize) -> Result<usize> {
-        let (first, second) = self.ringbuf.copy_within(distance, length);
-        self.writer.write_all(first)?;
-        self.writer.write_all(second)?;
-        Ok(first.len() + second.len())
+        ensure!(
+            distance > 0 && distance <= self.history_len(),
+            "invalid distance {}; only {} bytes are available (window size = {})",
+            distance,
+            self.history_len(),
+            self.window_size,
+        );
+        self.make_room(length)?;
+
+        let start = self.buf.len() - distance;
+        if distance >= length {
+            self.buf.extend_from_within(start..start + length);
+        } else {
+            // overlapping; the bytes copied so far repeat with the period of
+            // `distance`, so each copy can take all of them
+            let end = self.buf.len() + length;
+            while self.buf.len() < end {
+                let amount = std::cmp::min(self.buf.len() - start, end - self.buf.len());
+                self.buf.extend_from_within(start..start + amount);
+            }
+        }
+        Ok(length)
     }
 
     pub fn push(&mut self, value: u8) -> Result<()> {
-        self.ringbuf.push(value);
-        self.writer.write_all(&[value])?;
+        if self.buf.len() == self.buf.capacity() {
+            self.make_room(1)?;
+        }
+        self.buf.push(value);
         Ok(())
     }
 
+    /// writes out every byte produced so far and flushes the underlying writer
     pub fn flush(&mut self) -> std::io::Result<()> {
+        self.write_out()?;
         self.writer.flush()
     }
+
+    fn write_out(&mut self) -> std::io::Result<()> {
+        let pending = &self.buf[self.flushed..];
+        self.crc.update(pending);
+        self.writer.write_all(pending)?;
+        self.flushed = self.buf.len();
+        Ok(())
+    }
+
+    // makes sure `length` more bytes, up to the size of a chunk, fit in `buf`
+    // without growing it
+    fn make_room(&mut self, length: usize) -> std::io::Result<()> {
+        debug_assert!(length <= CHUNK_SIZE);
+        if self.buf.len() + length <= self.buf.capacity() {
+            return Ok(());
+        }
+
+        self.write_out()?;
+        let start = self.buf.len() - self.history_len();
+        self.buf.copy_within(start.., 0);
+        self.buf.truncate(self.buf.len() - start);
+        self.flushed = self.buf.len();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn crc32(data: &[u8]) -> u32 {
+        let mut crc = Crc32::new();
+        crc.update(data);
+        crc.sum()
+    }
+
+    #[test]
+    fn output_is_written_on_flush() {
+        let mut writer = Writer::new(vec![], 4);
+        writer.push(b'a').unwrap();
+        writer.push(b'b').unwrap();
+        assert_eq!(writer.copy_within(2, 5).unwrap(), 5);
+        assert!(writer.get_ref().is_empty());
+        assert_eq!(writer.crc32(), crc32(b"abababa"));
+
+        writer.flush().unwrap();
+        assert_eq!(writer.get_ref(), b"abababa");
+        assert_eq!(writer.crc32(), crc32(b"abababa"));
+    }
+
+    #[test]
+    fn reset_clears_history() {
+        let mut writer = Writer::new(vec![], 4);
+        writer.push(b'a').unwrap();
+        writer.reset().unwrap();
+        assert!(writer.copy_within(1, 1).is_err());
+        writer.push(b'b').unwrap();
+        assert_eq!(writer.crc32(), crc32(b"b"));
+        writer.flush().unwrap();
+        assert_eq!(writer.get_ref(), b"ab");
+    }
+
+    #[test]
+    fn history_survives_chunks() {
+        let mut writer = Writer::new(vec![], 32768);
+        writer.set_dictionary(b"dictionary");
+        let data: Vec<u8> = (0..CHUNK_SIZE as u32 * 3)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        writer.copy_from(&mut &data[..], data.len()).unwrap();
+        assert!(writer.copy_within(32769, 1).is_err());
+        writer.copy_within(32768, 300).unwrap();
+        writer.flush().unwrap();
+
+        let output = writer.get_ref();
+        assert_eq!(output.len(), data.len() + 300);
+        assert_eq!(output[..data.len()], data);
+        let start = data.len() - 32768;
+        assert_eq!(output[data.len()..], data[start..start + 300]);
+    }
 }
@@ -0,0 +1,335 @@
+// zlib format as specified in RFC 1950: a two-byte header (CMF, FLG), an
+// optional preset dictionary ID, a DEFLATE stream and Adler-32 of the
+// uncompressed data, all numbers being big endian.
+
+use crate::adler32::Adler32;
+use crate::compress;
+use crate::decompress::Inflate;
+use crate::dictionary::Dictionary;
+use crate::reader::Reader;
+use crate::writer::Writer;
+use crate::{CompressOptions, CompressionLevel};
+use anyhow::{bail, ensure, Result};
+use std::io::{BufRead, Read, Write};
+
+// CM = 8 (deflate)
+const DEFLATE: u8 = 8;
+// CINFO = 7, i.e. a window of 32 KiB
+const MAX_CINFO: u8 = 7;
+const FDICT: u8 = 0x20;
+
+// passes bytes through while keeping their Adler-32
+struct Adler32Reader<R> {
+    reader: R,
+    adler: Adler32,
+}
+
+impl<R> Read for Adler32Reader<R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let amount = self.reader.read(buf)?;
+        self.adler.update(&buf[..amount]);
+        Ok(amount)
+    }
+}
+
+struct Adler32Writer<W> {
+    writer: W,
+    adler: Adler32,
+}
+
+impl<W> Write for Adler32Writer<W>
+where
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let amount = self.writer.write(buf)?;
+        self.adler.update(&buf[..amount]);
+        Ok(amount)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+// FLEVEL, chosen the same way as zlib
+fn compression_level(opts: &CompressOptions) -> u8 {
+    let level = opts.level.level();
+    match level {
+        _ if opts.ultra.is_some() => 3,
+        _ if compress::is_fastest(opts) => 0,
+        _ if level < CompressionLevel::default().level() => 1,
+        _ if level == CompressionLevel::default().level() => 2,
+        _ => 3,
+    }
+}
+
+/// compresses everything `reader` yields into a zlib stream.
+///
+/// When `dictionary` is given, matches may refer to it and its ID is written
+/// (FDICT); the decompressor then needs the same dictionary.
+pub fn compress<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    dictionary: Option<&Dictionary>,
+    opts: &CompressOptions,
+) -> Result<()>
+where
+    R: Read,
+    W: Write,
+{
+    let cmf = (MAX_CINFO << 4) | DEFLATE;
+    let mut flg = compression_level(opts) << 6;
+    if dictionary.is_some() {
+        flg |= FDICT;
+    }
+    // FCHECK makes CMF * 256 + FLG a multiple of 31
+    flg += (31 - (u16::from(cmf) << 8 | u16::from(flg)) % 31) as u8 % 31;
+    writer.write_all(&[cmf, flg])?;
+
+    if let Some(dictionary) = dictionary {
+        // DICTID
+        writer.write_all(&dictionary.id().to_be_bytes())?;
+    }
+
+    let mut reader = Adler32Reader {
+        reader,
+        adler: Adler32::new(),
+    };
+    let dictionary = dictionary.map_or(&[][..], |d| d.as_bytes());
+    crate::compress::deflate(&mut reader, writer, dictionary, opts)?;
+
+    // ADLER32
+    writer.write_all(&reader.adler.sum().to_be_bytes())?;
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// decompresses a zlib stream.
+///
+/// `dictionary` must be given if the stream was compressed with one (FDICT).
+/// Only the first stream is read; a warning is printed if any bytes follow it.
+pub fn decompress<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    dictionary: Option<&Dictionary>,
+) -> Result<()>
+where
+    R: BufRead,
+    W: Write,
+{
+    let mut reader = Reader::new(reader);
+
+    let cmf = reader.next_byte()?;
+    let flg = reader.next_byte()?;
+
+    if (u16::from(cmf) << 8 | u16::from(flg)) % 31 != 0 {
+        bail!(
+            "wrong header check; CMF = {:#04x}, FLG = {:#04x} (CMF * 256 + FLG must be a multiple of 31)",
+            cmf,
+            flg,
+        );
+    }
+
+    let cm = cmf & 0x0f;
+    if cm != DEFLATE {
+        bail!(
+            "wrong compression method detected; CM = {:#x} (expected 0x08)",
+            cm,
+        );
+    }
+
+    let cinfo = cmf >> 4;
+    ensure!(
+        cinfo <= MAX_CINFO,
+        "invalid window size; CINFO = {} (must be 7 or less)",
+        cinfo,
+    );
+    // distances beyond the window are rejected by `Writer`
+    let window_size = 1 << (cinfo + 8);
+
+    let mut writer = Writer::new(
+        Adler32Writer {
+            writer,
+            adler: Adler32::new(),
+        },
+        window_size,
+    );
+
+    if flg & FDICT > 0 {
+        let dict_id_bytes = [
+            reader.next_byte()?,
+            reader.next_byte()?,
+            reader.next_byte()?,
+            reader.next_byte()?,
+        ];
+        let dict_id = u32::from_be_bytes(dict_id_bytes);
+
+        match dictionary {
+            None => bail!("preset dictionary required; DICTID = {:#010x}", dict_id),
+            Some(dictionary) => {
+                dictionary.verify(dict_id)?;
+                writer.set_dictionary(dictionary.as_bytes());
+            }
+        }
+    }
+
+    Inflate::new().decode(&mut reader, &mut writer, usize::MAX)?;
+
+    reader.ensure_byte_boundary()?;
+    let adler_bytes = [
+        reader.next_byte()?,
+        reader.next_byte()?,
+        reader.next_byte()?,
+        reader.next_byte()?,
+    ];
+    let expected = u32::from_be_bytes(adler_bytes);
+    writer.flush()?;
+    let actual = writer.get_ref().adler.sum();
+    if expected != actual {
+        bail!(
+            "Adler-32 mismatch; expected = {:#010x}, actual = {:#010x}",
+            expected,
+            actual,
+        );
+    }
+
+    if reader.has_more_bytes()? {
+        eprintln!("warning: trailing garbage ignored");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::sample;
+    use crate::Strategy;
+
+    fn compress_with(
+        data: &[u8],
+        dictionary: Option<&Dictionary>,
+        opts: &CompressOptions,
+    ) -> Vec<u8> {
+        let mut compressed = vec![];
+        compress(&mut &data[..], &mut compressed, dictionary, opts).unwrap();
+        compressed
+    }
+
+    fn decompress_with(compressed: &[u8], dictionary: Option<&Dictionary>) -> Result<Vec<u8>> {
+        let mut decompressed = vec![];
+        decompress(&mut &compressed[..], &mut decompressed, dictionary)?;
+        Ok(decompressed)
+    }
+
+    #[test]
+    fn roundtrips() {
+        let data = sample(10000);
+        for level in [0, 1, 6, 9] {
+            let opts = CompressOptions {
+                level: CompressionLevel::new(level).unwrap(),
+                ..Default::default()
+            };
+            let compressed = compress_with(&data, None, &opts);
+            assert_eq!(decompress_with(&compressed, None).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn known_stream_is_decoded() {
+        // zlib.compress(b"hello, hello, hello")
+        let compressed = [
+            0x78, 0x9c, 0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0xd7, 0x51, 0xc8, 0x40, 0xa2, 0x00, 0x44,
+            0x28, 0x06, 0xd5,
+        ];
+        assert_eq!(
+            decompress_with(&compressed, None).unwrap(),
+            b"hello, hello, hello"
+        );
+    }
+
+    #[test]
+    fn header_is_checked() {
+        let compressed = compress_with(b"hello", None, &Default::default());
+        assert_eq!(&compressed[..2], &[0x78, 0x9c]);
+
+        // FLEVEL = 0 as zlib writes for the strategies without lazy matching
+        for strategy in [Strategy::HuffmanOnly, Strategy::Rle, Strategy::Fixed] {
+            let opts = CompressOptions {
+                strategy,
+                ..Default::default()
+            };
+            let header = &compress_with(b"hello", None, &opts)[..2];
+            assert_eq!(header, &[0x78, 0x01], "{:?}", strategy);
+        }
+
+        let mut broken = compressed.clone();
+        broken[1] ^= 0x01;
+        assert!(decompress_with(&broken, None).is_err());
+
+        // CINFO = 8 with a valid FCHECK
+        let err = decompress_with(&[0x88, 0x98, 0x03, 0x00], None).unwrap_err();
+        assert!(err.to_string().contains("CINFO"), "{}", err);
+    }
+
+    #[test]
+    fn distance_beyond_window_is_rejected() {
+        let data = sample(10000);
+        let mut compressed = compress_with(&data, None, &Default::default());
+        // claim a window of 256 bytes; FCHECK stays valid as FLG is adjusted
+        compressed[0] = 0x08;
+        compressed[1] = 0;
+        compressed[1] += (31 - (0x0800u16 % 31)) as u8 % 31;
+
+        let err = decompress_with(&compressed, None).unwrap_err();
+        assert!(err.to_string().contains("invalid distance"), "{}", err);
+    }
+
+    #[test]
+    fn adler32_is_verified() {
+        let mut compressed = compress_with(b"hello", None, &Default::default());
+        let len = compressed.len();
+        compressed[len - 1] ^= 0x01;
+
+        let err = decompress_with(&compressed, None).unwrap_err();
+        assert!(err.to_string().contains("Adler-32 mismatch"), "{}", err);
+    }
+
+    #[test]
+    fn only_the_first_stream_is_read() {
+        let mut compressed = compress_with(b"hello", None, &Default::default());
+        compressed.extend_from_slice(&compress_with(b"world", None, &Default::default()));
+        assert_eq!(decompress_with(&compressed, None).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn preset_dictionary_is_required_and_checked() {
+        let data = sample(10000);
+        let dictionary = Dictionary::new(&data[..2000]);
+        let compressed = compress_with(&data[2000..6000], Some(&dictionary), &Default::default());
+        let without = compress_with(&data[2000..6000], None, &Default::default());
+        assert!(compressed.len() < without.len());
+        assert_eq!(compressed[1] & FDICT, FDICT);
+        assert_eq!(&compressed[2..6], &dictionary.id().to_be_bytes());
+
+        assert_eq!(
+            decompress_with(&compressed, Some(&dictionary)).unwrap(),
+            &data[2000..6000]
+        );
+        let err = decompress_with(&compressed, None).unwrap_err();
+        assert!(err.to_string().contains("dictionary required"), "{}", err);
+        let wrong = Dictionary::new(&data[..1000]);
+        let err = decompress_with(&compressed, Some(&wrong)).unwrap_err();
+        assert!(
+            err.to_string().contains("wrong preset dictionary"),
+            "{}",
+            err
+        );
+    }
+}